@@ -0,0 +1,81 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::{json, Value};
+use tree_tables::TreeTable;
+
+const MATERIAL_COL: &str = "2387c84a-2c68-405e-a342-d94a1dde6408";
+const TIME_COL: &str = "94869fe6-c736-4c88-be7f-8084679d78fc";
+const PRICE_COL: &str = "5aafbaab-6c03-4e8f-9fc4-cfb66ed2fb16";
+
+fn row(name: String, col_data: Value, children: Vec<Value>) -> Value {
+    json!({
+        "id": name.clone(),
+        "name": name,
+        "col_data": col_data,
+        "children": children,
+        "enabled": true,
+        "multiplier": 1.0,
+    })
+}
+
+// 100 groups with 10 subgroups with 100 leaves each
+fn generate_document() -> String {
+    let groups = (0..100)
+        .map(|g| {
+            let subgroups = (0..10)
+                .map(|s| {
+                    let leaves = (0..100)
+                        .map(|l| {
+                            let col_data = json!({ MATERIAL_COL: l as f64, TIME_COL: 0.5 });
+                            row(format!("{g}.{s}.{l}"), col_data, vec![])
+                        })
+                        .collect();
+                    row(format!("{g}.{s}"), json!({}), leaves)
+                })
+                .collect();
+            row(format!("{g}"), json!({}), subgroups)
+        })
+        .collect();
+
+    json!({
+        "title_text": "Generated",
+        "column_configs": [
+            { "id": MATERIAL_COL, "caption": "Material", "unit": "€", "col_type": "Number" },
+            { "id": TIME_COL, "caption": "Time", "unit": "h", "col_type": "Number" },
+            {
+                "id": PRICE_COL,
+                "caption": "Price",
+                "unit": "€",
+                "col_type": { "MultiplyByFactor": [MATERIAL_COL, 100.0] }
+            },
+        ],
+        "root_row": row("∑".to_owned(), json!({}), groups),
+    })
+    .to_string()
+}
+
+fn large_document(c: &mut Criterion) {
+    let json = generate_document();
+
+    c.bench_function("load 100k rows", |b| {
+        b.iter(|| {
+            let mut tree_table = TreeTable::from_json(&json).unwrap();
+            tree_table.update();
+            tree_table
+        })
+    });
+
+    c.bench_function("update 100k rows without changes", |b| {
+        b.iter_batched_ref(
+            || {
+                let mut tree_table = TreeTable::from_json(&json).unwrap();
+                tree_table.update();
+                tree_table
+            },
+            |tree_table| tree_table.update(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, large_document);
+criterion_main!(benches);
@@ -1,16 +1,42 @@
 use egui::*;
 use egui_keybind::{Bind, Shortcut};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::io::prelude::*;
+use std::mem::discriminant;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
 use std::vec::Vec;
 use uuid::Uuid;
 
+use crate::autosave;
+use crate::dates::{self, format_date, parse_date};
+use crate::diff::{RowDiff, Status, TableDiff};
+use crate::library::{Library, Template};
+use crate::merge::{merge, Conflict};
+use crate::units;
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const VALID_FILE_EXTENSIONS: [&'static str; 3] = ["tt", "json", "ttable"];
+const VALID_FILE_EXTENSIONS: [&str; 3] = ["tt", "json", "ttable"];
+
+const DEFAULT_COLUMN_WIDTH: f32 = 120.0;
+const MIN_COLUMN_WIDTH: f32 = 40.0;
+const DEFAULT_NAME_COLUMN_WIDTH: f32 = 300.0;
+const BUTTON_COLUMN_WIDTH: f32 = 40.0;
+const INDENT_WIDTH: f32 = 10.0;
+const MAX_RECENT_FILES: usize = 10;
 
-fn format_float(mut x: f64, unit: Option<&str>, show_decimal: bool) -> String {
-    if show_decimal == false {
+const ADD_CHILD_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Enter);
+const DELETE_ROW_SHORTCUT: KeyboardShortcut =
+    KeyboardShortcut::new(Modifiers::COMMAND, Key::Delete);
+const UNDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+const REDO_SHORTCUT: KeyboardShortcut =
+    KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z);
+const TOGGLE_EXPAND_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::NONE, Key::Space);
+
+pub(crate) fn format_float(mut x: f64, unit: Option<&str>, show_decimal: bool) -> String {
+    if !show_decimal {
         x = x.round();
     }
 
@@ -26,22 +52,45 @@ fn format_float(mut x: f64, unit: Option<&str>, show_decimal: bool) -> String {
         .unwrap()
         .join(".");
 
-    let suffix = if unit.is_some() {
-        " ".to_owned() + unit.unwrap()
+    let suffix = if let Some(unit) = unit {
+        " ".to_owned() + unit
     } else {
         "".to_owned()
     };
 
-    if show_decimal == false {
-        return int_str + suffix.as_str();
+    if !show_decimal {
+        int_str + suffix.as_str()
     } else {
         let decimal_part = x - int_part as f64;
         let decimal_part_int = (decimal_part * 100.0).round() as i64;
-        return int_str + "," + format!("{:02}", decimal_part_int).as_str() + suffix.as_str();
+        int_str + "," + format!("{:02}", decimal_part_int).as_str() + suffix.as_str()
+    }
+}
+
+fn parse_float(s: &str) -> Option<f64> {
+    let s_cleaned = String::from(s).replace('.', "").replace(',', ".");
+    s_cleaned.trim().parse::<f64>().ok()
+}
+
+// Splits "90 min" into the number and the unit, which is None if the text is just a number.
+// Numbers are German like everywhere in the app, e.g. "1.500,5". A '.' which can't separate
+// thousands, e.g. in "1.5", is rejected instead of being dropped by `parse_float`.
+fn parse_quantity(s: &str) -> Option<(f64, Option<&str>)> {
+    let s = s.trim();
+    let is_number = |c: char| c.is_ascii_digit() || "+-.,".contains(c) || c.is_whitespace();
+    let unit_start = s.find(|c: char| !is_number(c)).unwrap_or(s.len());
+    let (number, unit) = s.split_at(unit_start);
+    let thousands = (number.split('.').skip(1))
+        .all(|group| group.chars().take_while(char::is_ascii_digit).count() == 3);
+    if !thousands {
+        return None;
     }
+    let unit = unit.trim();
+    Some((parse_float(number)?, (!unit.is_empty()).then_some(unit)))
 }
 
 use String as ColumnID;
+use String as RowID;
 
 #[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Debug)]
 pub enum ColumnType {
@@ -52,407 +101,4756 @@ pub enum ColumnType {
     Text,
 
     // Multiply the number from the given column by the given factor
-    MultiplyByFactor(ColumnID, f64),
+    MultiplyByFactor(ColumnID, Factor),
 
     // Sum up the values of the given columns
     RowSum(Vec<ColumnID>),
+
+    // Amounts of money, summed up in the currency given by the unit of the column
+    Currency,
+
+    // Calendar dates, parents show the earliest or latest date of their children
+    Date(DateRollup),
+
+    // Durations in the unit of the column, parents sum them up like numbers
+    Duration,
+
+    // A bar from the date of the first to the date of the second column
+    Gantt(ColumnID, ColumnID),
+
+    // A checkbox, parents count the checked leaves
+    Boolean,
+
+    // One of the given values, parents count the leaves per value
+    Enum(Vec<String>),
+
+    // The values of the input column of the leaves which meet the condition
+    SumIf(ColumnID, Condition),
+
+    // The value of the input column in percent of the parent's value
+    ShareOfParent(ColumnID),
+
+    // The value of the input column in percent of the root row's value
+    ShareOfTotal(ColumnID),
+
+    // The value of the input column with the steps applied one after the other
+    Markup(ColumnID, Vec<MarkupStep>),
+}
+
+/// One step of a markup chain, e.g. `Overhead +12 %` or `Shipping +25 €`.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub struct MarkupStep {
+    pub(crate) name: String,
+
+    // A percentage of the previous result, otherwise an amount which is added to it
+    pub(crate) percent: bool,
+    pub(crate) value: Factor,
+}
+
+impl Default for MarkupStep {
+    fn default() -> Self {
+        Self {
+            name: "".to_owned(),
+            percent: true,
+            value: Factor::Value(0.0),
+        }
+    }
+}
+
+impl MarkupStep {
+    fn apply(&self, value: f64, parameters: &[Parameter]) -> f64 {
+        let amount = self.value.resolve(parameters);
+        match self.percent {
+            true => value * (1.0 + amount / 100.0),
+            false => value + amount,
+        }
+    }
+}
+
+/// Compares the value of a leaf in a Boolean or Enum column, e.g. `Supplier = X` or
+/// `Status != done`.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Condition {
+    pub(crate) col_id: ColumnID,
+    pub(crate) negated: bool,
+
+    // Empty for leaves without a value, "true" for checked Boolean cells
+    pub(crate) value: String,
+}
+
+impl Condition {
+    fn matches(&self, row: &RowData) -> bool {
+        let value = row.categories.get(&self.col_id).map_or("", |value| value);
+        (value == self.value) != self.negated
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Clone, Copy, Debug)]
+pub enum DateRollup {
+    Earliest,
+    Latest,
+}
+
+/// A fixed number or the name of a parameter of the document.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(untagged)] // Documents without parameters store the plain number
+pub enum Factor {
+    Value(f64),
+    Parameter(String),
+}
+
+impl Factor {
+    fn resolve(&self, parameters: &[Parameter]) -> f64 {
+        match self {
+            Factor::Value(value) => *value,
+            Factor::Parameter(name) => parameter_value(parameters, name),
+        }
+    }
+}
+
+/// A named value of the document, e.g. a VAT rate or an hourly rate.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Parameter {
+    pub(crate) name: String,
+    pub(crate) value: f64,
+}
+
+// A missing parameter counts as 0 for factors and multipliers, so that the broken reference
+// stands out
+fn parameter_value(parameters: &[Parameter], name: &str) -> f64 {
+    (parameters.iter())
+        .find(|parameter| parameter.name == name)
+        .map_or(0.0, |parameter| parameter.value)
+}
+
+/// The value of one unit of a currency in a common reference currency, e.g. EUR = 1, USD = 0.92.
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Default)]
+#[serde(default)]
+pub struct ExchangeRate {
+    pub(crate) currency: String,
+    pub(crate) rate: f64,
+}
+
+// Reads lines like `USD;0,92` or `USD,0.92`, lines which don't parse, e.g. a header, are skipped
+fn parse_exchange_rates(text: &str) -> Vec<ExchangeRate> {
+    (text.lines())
+        .filter_map(|line| {
+            let (currency, rate) = match line.contains(';') {
+                true => line.split_once(';')?,
+                false => line.split_once(',')?,
+            };
+            let rate = rate
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .or_else(|| parse_float(rate))?;
+            Some(ExchangeRate {
+                currency: currency.trim().to_owned(),
+                rate,
+            })
+        })
+        .collect()
+}
+
+/// The factor which converts values of one unit or currency to the other, None if they are
+/// incompatible or an exchange rate is missing.
+fn conversion_factor(from: &str, to: &str, exchange_rates: &[ExchangeRate]) -> Option<f64> {
+    let rate = |currency: &str| {
+        (exchange_rates.iter())
+            .find(|rate| rate.currency == currency.trim())
+            .map(|rate| rate.rate)
+            .filter(|rate| *rate != 0.0)
+    };
+    units::conversion_factor(from, to).or_else(|| Some(rate(from)? / rate(to)?))
 }
 
 impl ColumnType {
-    fn is_editable(&self) -> bool {
+    pub(crate) fn is_editable(&self) -> bool {
         match self {
             ColumnType::Number => true,
             ColumnType::Text => true,
             ColumnType::MultiplyByFactor(_, _) => false,
             ColumnType::RowSum(_) => false,
+            ColumnType::Currency => true,
+            ColumnType::Date(_) => true,
+            ColumnType::Duration => true,
+            ColumnType::Gantt(_, _) => false,
+            ColumnType::Boolean => true,
+            ColumnType::Enum(_) => true,
+            ColumnType::SumIf(_, _) => false,
+            ColumnType::ShareOfParent(_) => false,
+            ColumnType::ShareOfTotal(_) => false,
+            ColumnType::Markup(_, _) => false,
+        }
+    }
+
+    // Shares are calculated top-down after all other values, see `RowData::update_shares`
+    fn is_share(&self) -> bool {
+        matches!(
+            self,
+            ColumnType::ShareOfParent(_) | ColumnType::ShareOfTotal(_)
+        )
+    }
+
+    // Columns whose values are categories, stored in `RowData::categories`
+    fn is_categorical(&self) -> bool {
+        matches!(self, ColumnType::Boolean | ColumnType::Enum(_))
+    }
+
+    // Columns whose leaves hold a plain amount in the column unit, which bulk edits can set
+    fn is_amount(&self) -> bool {
+        matches!(self, ColumnType::Number | ColumnType::Currency)
+    }
+
+    // The columns which the values of this column are calculated from
+    fn input_columns(&self) -> Vec<&ColumnID> {
+        match self {
+            ColumnType::Number
+            | ColumnType::Text
+            | ColumnType::Currency
+            | ColumnType::Date(_)
+            | ColumnType::Duration
+            | ColumnType::Boolean
+            | ColumnType::Enum(_) => vec![],
+            ColumnType::Gantt(start_col_id, end_col_id) => vec![start_col_id, end_col_id],
+            ColumnType::MultiplyByFactor(input_col_id, _) => vec![input_col_id],
+            ColumnType::SumIf(input_col_id, _) => vec![input_col_id],
+            ColumnType::ShareOfParent(input_col_id) => vec![input_col_id],
+            ColumnType::ShareOfTotal(input_col_id) => vec![input_col_id],
+            ColumnType::Markup(input_col_id, _) => vec![input_col_id],
+            ColumnType::RowSum(input_col_ids) => input_col_ids.iter().collect(),
         }
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+// Orders the columns so that every column comes after its inputs.
+// Columns in a cycle keep their order and use the values of the last calculation.
+fn calculation_order(column_configs: &[ColumnConfig]) -> Vec<&ColumnConfig> {
+    let mut order: Vec<&ColumnConfig> = Vec::with_capacity(column_configs.len());
+    let mut rest: Vec<&ColumnConfig> = column_configs.iter().collect();
+
+    while !rest.is_empty() {
+        let is_pending = |col_id: &ColumnID| rest.iter().any(|cfg| cfg.id == *col_id);
+        let ready = rest.iter().position(|cfg| {
+            let inputs = cfg.col_type.input_columns();
+            inputs
+                .into_iter()
+                .all(|id| *id == cfg.id || !is_pending(id))
+        });
+        order.push(rest.remove(ready.unwrap_or(0)));
+    }
+    order
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
 pub struct ColumnConfig {
-    id: ColumnID,
-    caption: String,
-    unit: String,
-    col_type: ColumnType,
+    pub(crate) id: ColumnID,
+    pub(crate) caption: String,
+    pub(crate) unit: String,
+    pub(crate) col_type: ColumnType,
+
+    // Hidden columns are still calculated, but neither shown nor exported
+    hidden: bool,
+
+    // The width is a view preference, see `TreeTablesApp::column_widths`
+    #[serde(skip)]
+    width: f32,
 }
 
 enum Action {
     Modified,
     Remove,
-}
+    AddChild,
 
-#[derive(serde::Deserialize, serde::Serialize)]
-pub enum DataElement {
-    Number(f32),
-    String(String),
+    // The row was expanded or collapsed
+    Expanded,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
-#[serde(default)] // if we add new fields, give them default values when deserializing old state
-pub struct RowData {
-    name: String,
-
-    // data_elements: HashMap<ColumnID, DataElement>,
-    col_data: HashMap<ColumnID, f64>,
-    children: Vec<RowData>,
+/// One line of the table as it is laid out on screen.
+enum TableLine {
+    Row {
+        row_path: Vec<usize>,
+        parent_enabled: bool,
+    },
 
-    enabled: bool,
+    // The button to add a child to the row at the given path
+    AddChild {
+        row_path: Vec<usize>,
+    },
+}
 
-    multiplier: f64,
+/// Shows only the matching rows and their ancestors.
+#[derive(Clone, PartialEq)]
+enum RowFilter {
+    // Leaves with the given value in a Boolean or Enum column. None matches the leaves without a
+    // value, i.e. unchecked Boolean cells.
+    Category {
+        col_id: ColumnID,
+        value: Option<String>,
+    },
 
-    // UI State
-    expanded: bool,
-    edit_name: bool,
+    // Rows with the tag, together with their subtrees
+    Tag(String),
 }
 
-impl Default for RowData {
-    fn default() -> Self {
-        Self {
-            name: "".to_owned(),
-            col_data: HashMap::new(),
-            children: vec![],
-            edit_name: false,
-            expanded: true,
-            enabled: true,
-            multiplier: 1.0,
+impl RowFilter {
+    fn matches(&self, row: &RowData) -> bool {
+        match self {
+            RowFilter::Category { col_id, value } if row.children.is_empty() => {
+                row.categories.get(col_id) == value.as_ref()
+            }
+            RowFilter::Category { .. } => row.children.iter().any(|child| self.matches(child)),
+            RowFilter::Tag(tag) => {
+                row.tags.contains(tag) || row.children.iter().any(|child| self.matches(child))
+            }
         }
     }
-}
 
-impl RowData {
-    fn update(&mut self, column_configs: &Vec<ColumnConfig>) {
-        // Update all children
-        for child in self.children.iter_mut() {
-            child.update(column_configs);
+    // The filter for the children of the row, None if they are all shown
+    fn for_children(&self, row: &RowData) -> Option<&RowFilter> {
+        match self {
+            RowFilter::Tag(tag) if row.tags.contains(tag) => None,
+            _ => Some(self),
         }
+    }
+}
 
-        // Update the actual values
-        for col_cfg in column_configs.iter() {
-            let col_id = &col_cfg.id;
+/// Position of the keyboard cursor in the table.
+///
+/// Column 0 is the row name, the data columns start at 1.
+#[derive(Clone, PartialEq, Debug)]
+struct CellCursor {
+    row_path: Vec<usize>,
+    col_idx: usize,
+}
 
-            if !self.children.is_empty() {
-                let mut sum = 0.0;
-                for child in self.children.iter_mut() {
-                    match &col_cfg.col_type {
-                        ColumnType::Number => {
-                            if child.enabled {
-                                sum +=
-                                    child.multiplier * child.col_data.get(col_id).unwrap_or(&0.0);
-                            }
-                        }
-                        ColumnType::Text => (),
-                        ColumnType::MultiplyByFactor(input_col_id, factor) => {
-                            let value = child.col_data.get(input_col_id).unwrap_or(&0.0) * factor;
-                            // 1. Update the sum
-                            if child.enabled {
-                                sum += value;
-                            }
+#[derive(Default)]
+struct TableNavigation {
+    cursor: Option<CellCursor>,
 
-                            // 2. Update the value itself
-                            child.col_data.insert(col_id.clone(), value);
-                        }
-                        ColumnType::RowSum(_) => {
-                            todo!("Pleas implement the RowSum function!")
-                        }
-                    }
-                }
-                self.col_data.insert(col_id.clone(), sum);
-            }
+    // Some while the cell under the cursor is edited via the keyboard
+    edit_buffer: Option<String>,
+
+    // Tab also moves egui's widget focus, which has to be undone after the frame
+    drop_focus: bool,
+
+    scroll_to_cursor: bool,
+
+    // Rows selected for bulk operations
+    selected_rows: Vec<Vec<usize>>,
+    selection_anchor: Option<Vec<usize>>,
+
+    // Row name clicked in the current frame, applied to the selection after rendering
+    clicked_row: Option<(Vec<usize>, Modifiers)>,
+
+    // Row whose notes, tags and links are edited
+    details_row: Option<Vec<usize>>,
+}
+
+impl TableNavigation {
+    fn is_cursor(&self, row_path: &[usize], col_idx: usize) -> bool {
+        match &self.cursor {
+            Some(cursor) => cursor.row_path == row_path && cursor.col_idx == col_idx,
+            None => false,
         }
     }
 
-    fn render(
-        &mut self,
-        ui: &mut Ui,
-        column_configs: &Vec<ColumnConfig>,
-        indent_level: i32,
-        parent_enabled: bool,
-        show_decimals: bool,
-    ) -> Option<Action> {
-        let mut action = None;
+    fn is_editing(&self, row_path: &[usize], col_idx: usize) -> bool {
+        self.edit_buffer.is_some() && self.is_cursor(row_path, col_idx)
+    }
 
-        ui.horizontal(|ui| {
-            ui.add_space(10.0 * indent_level as f32);
-            ui.expand_button(&mut self.expanded);
-            if indent_level > 0 {
-                ui.checkbox(&mut self.enabled, "");
+    fn set_cursor(&mut self, row_path: &[usize], col_idx: usize) {
+        self.cursor = Some(CellCursor {
+            row_path: row_path.to_vec(),
+            col_idx,
+        });
+        self.edit_buffer = None;
+    }
 
-                if ui
-                    .add_enabled(
-                        self.enabled && parent_enabled,
-                        egui::DragValue::new(&mut self.multiplier)
-                            .speed(1.0)
-                            .suffix("x")
-                            .custom_formatter(|n, _| format_float(n, None, show_decimals))
-                            .custom_parser(|s| {
-                                let s_cleaned = String::from(s).replace(".", "").replace(",", ".");
-                                return match s_cleaned.parse::<f64>() {
-                                    Ok(x) => Some(x),
-                                    Err(_) => None,
-                                };
-                            }),
-                    )
-                    .changed()
-                {
-                    action = Some(Action::Modified);
-                }
+    fn is_selected(&self, row_path: &[usize]) -> bool {
+        self.selected_rows.iter().any(|p| p == row_path)
+    }
+
+    fn clear_selection(&mut self) {
+        self.selected_rows.clear();
+        self.selection_anchor = None;
+    }
+
+    /// Updates the selection after a click on the given row.
+    ///
+    /// Ctrl/Cmd-click toggles a single row, Shift-click selects the visible range from the anchor.
+    fn click_row(
+        &mut self,
+        row_path: Vec<usize>,
+        modifiers: Modifiers,
+        visible_paths: &[Vec<usize>],
+    ) {
+        if modifiers.shift {
+            let anchor = self.selection_anchor.clone().unwrap_or(row_path.clone());
+            let anchor_pos = visible_paths.iter().position(|p| *p == anchor);
+            let click_pos = visible_paths.iter().position(|p| *p == row_path);
+            if let (Some(a), Some(b)) = (anchor_pos, click_pos) {
+                self.selected_rows = visible_paths[a.min(b)..=a.max(b)].to_vec();
             }
-            if self.edit_name {
-                if ui.text_edit_singleline(&mut self.name).lost_focus() {
-                    if !self.name.is_empty() {
-                        self.edit_name = false;
-                    }
-                }
+            self.selection_anchor = Some(anchor);
+        } else if modifiers.command {
+            if self.is_selected(&row_path) {
+                self.selected_rows.retain(|p| *p != row_path);
             } else {
-                if ui.label(self.name.clone() + ":").double_clicked() {
-                    self.edit_name = true;
-                }
+                self.selected_rows.push(row_path.clone());
             }
-        });
+            self.selection_anchor = Some(row_path);
+        } else {
+            self.selected_rows = vec![row_path.clone()];
+            self.selection_anchor = Some(row_path);
+        }
+    }
+}
 
-        let leaf_node = self.children.is_empty();
+fn highlight_cursor(ui: &Ui, resp: &Response) {
+    ui.painter()
+        .rect_stroke(resp.rect.expand(2.0), 2.0, ui.visuals().selection.stroke);
+}
 
-        for col_cfg in column_configs.iter() {
-            let col_id = &col_cfg.id;
-            let value = *self.col_data.get(col_id).unwrap_or(&0.0);
-            let unit = col_cfg.unit.clone();
+// Runs `add_contents` in a Ui covering a single table cell. The id is derived from the row,
+// so that the widget state (e.g. an ongoing DragValue edit) stays with the row while scrolling.
+fn show_cell<R>(
+    ui: &Ui,
+    rect: Rect,
+    clip_rect: Rect,
+    id: Id,
+    add_contents: impl FnOnce(&mut Ui) -> R,
+) -> R {
+    let clip_rect = clip_rect.intersect(rect.expand(2.0));
+    let mut cell_ui = Ui::new(ui.ctx().clone(), ui.layer_id(), id, rect, clip_rect);
+    cell_ui
+        .with_layout(egui::Layout::left_to_right(Align::Center), add_contents)
+        .inner
+}
 
-            if self.col_data.get(col_id).is_none() {
-                self.col_data.insert(col_id.clone(), 0.0);
-            }
+/// Screen positions of the table parts, shared by the rendering of a frame.
+struct TableLayout {
+    // Top left corner of the whole (scrolled) table content
+    origin: Pos2,
 
-            ui.add_space(10.0 * indent_level as f32);
+    // The visible part of the table
+    view: Rect,
 
-            let editable = leaf_node && col_cfg.col_type.is_editable();
+    // Bottom right corner of the sticky header cell of the name column
+    split: Pos2,
 
-            if editable {
-                if ui
-                    .add_enabled(
-                        self.enabled && parent_enabled,
-                        egui::DragValue::new(self.col_data.get_mut(col_id).unwrap())
-                            .speed(1.0)
-                            .suffix(format!(" {unit}"))
-                            .custom_formatter(|n, _| format_float(n, None, show_decimals))
-                            .custom_parser(|s| {
-                                let s_cleaned = String::from(s).replace(".", "").replace(",", ".");
-                                return match s_cleaned.parse::<f64>() {
-                                    Ok(x) => Some(x),
-                                    Err(_) => None,
-                                };
-                            }),
-                    )
-                    .changed()
-                {
-                    action = Some(Action::Modified);
-                }
-            } else {
-                ui.label(format_float(value, Some(unit.as_str()), show_decimals));
+    row_height: f32,
+
+    // Index into the column configs, x offset within the body and width of the shown columns
+    columns: Vec<(usize, f32, f32)>,
+
+    // x offset of the button column within the body
+    button_x: f32,
+}
+
+impl TableLayout {
+    // The shown columns with their x offset within the body, and the width of all of them
+    fn shown_columns(
+        column_configs: &[ColumnConfig],
+        spacing: f32,
+    ) -> (Vec<(usize, f32, f32)>, f32) {
+        let mut columns = vec![];
+        let mut x = 0.0;
+        for (col_idx, cfg) in column_configs.iter().enumerate() {
+            if !cfg.hidden {
+                columns.push((col_idx, x, cfg.width));
+                x += cfg.width + spacing;
             }
         }
+        (columns, x)
+    }
 
-        // Remove row button
-        if ui.button("🗑").clicked() {
-            action = Some(Action::Remove);
-        }
-        ui.end_row();
+    // The part of the table to scroll to for a cell, including the sticky header above it
+    // and the name column left of it. `col_idx` counts the name column as well.
+    fn cell_scroll_rect(&self, line_idx: usize, col_idx: usize) -> Rect {
+        let y = self.origin.y + self.row_height * (line_idx + 1) as f32;
+        let x = (self.columns.iter())
+            .find(|(idx, _, _)| *idx + 1 == col_idx)
+            .map_or(self.origin.x, |(_, x, width)| self.body_x(*x) + width);
+        Rect::from_min_max(
+            pos2(self.origin.x, y - self.row_height),
+            pos2(x, y + self.row_height),
+        )
+    }
 
-        // Optionally add the children
-        if self.expanded {
-            let mut remove_idx = None;
-            for (i, child) in self.children.iter_mut().enumerate() {
-                match child.render(
-                    ui,
-                    column_configs,
-                    indent_level + 1,
-                    self.enabled,
-                    show_decimals,
-                ) {
-                    Some(Action::Remove) => remove_idx = Some(i),
-                    Some(Action::Modified) => action = Some(Action::Modified),
-                    None => (),
-                }
-            }
+    fn body_x(&self, x: f32) -> f32 {
+        self.origin.x + (self.split.x - self.view.min.x) + x
+    }
 
-            if let Some(i) = remove_idx {
-                self.children.remove(i);
+    fn body_rect(&self, x: f32, y: f32, width: f32) -> Rect {
+        Rect::from_min_size(pos2(self.body_x(x), y), vec2(width, self.row_height))
+    }
 
-                // Removing a children, means that something changed!
-                action = Some(Action::Modified);
-            }
+    fn header_clip(&self) -> Rect {
+        Rect::from_x_y_ranges(self.split.x..=self.view.max.x, self.view.y_range())
+    }
 
-            // Button to add a new element at the same level
-            ui.horizontal(|ui| {
-                ui.add_space(10.0 * (indent_level + 1) as f32);
-                if ui.button("+").clicked() {
-                    let mut new_col_data = HashMap::new();
-                    for col_cfg in column_configs.iter() {
-                        if self.children.is_empty() {
-                            new_col_data.insert(
-                                col_cfg.id.clone(),
-                                *self.col_data.get(&col_cfg.id).unwrap_or(&0.0),
-                            );
-                        } else {
-                            new_col_data.insert(col_cfg.id.clone(), 0.0);
-                        }
-                    }
-                    self.children.push(RowData {
-                        name: "".to_owned(),
-                        col_data: new_col_data,
-                        children: vec![],
-                        expanded: false,
-                        edit_name: true,
-                        enabled: true,
-                        multiplier: 1.0,
-                    });
+    fn name_clip(&self) -> Rect {
+        Rect::from_x_y_ranges(self.view.x_range(), self.split.y..=self.view.max.y)
+    }
 
-                    action = Some(Action::Modified);
-                }
-            });
-            ui.end_row();
-        }
+    fn body_clip(&self) -> Rect {
+        Rect::from_min_max(self.split, self.view.max)
+    }
+}
+
+/// Input values of the bulk operations on the selected rows.
+struct BulkEdit {
+    multiplier: f64,
+    col_idx: usize,
+    value: f64,
+}
 
-        return action;
+impl Default for BulkEdit {
+    fn default() -> Self {
+        Self {
+            multiplier: 1.0,
+            col_idx: 0,
+            value: 0.0,
+        }
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
-struct TreeTable {
-    title_text: String,
-    column_configs: Vec<ColumnConfig>,
-    root_row: RowData,
+/// The differences shown in the compare window.
+struct CompareView {
+    old_name: String,
+    new_name: String,
+    diff: TableDiff,
+    show_unchanged: bool,
 }
 
-impl TreeTable {
-    fn save_to_file(&self, file_path: &str) {
-        if let Ok(mut file) = File::create(file_path) {
-            let _res = file.write(serde_json::to_string(&self).unwrap().as_bytes());
+fn diff_status_color(ui: &Ui, status: Status) -> Color32 {
+    match status {
+        Status::Unchanged => ui.visuals().text_color(),
+        Status::Added => Color32::from_rgb(80, 160, 80),
+        Status::Removed => ui.visuals().error_fg_color,
+        Status::Renamed | Status::Changed => ui.visuals().warn_fg_color,
+    }
+}
+
+// Adds the grid rows of a row of the compare window and its descendants
+fn show_diff_row(ui: &mut Ui, view: &CompareView, row: &RowDiff, depth: usize) {
+    if !view.show_unchanged && !row.has_changes() {
+        return;
+    }
+
+    let color = diff_status_color(ui, row.status);
+    let mut name = format!("{}{}", "  ".repeat(depth), row.name);
+    if let Some(old_name) = &row.old_name {
+        name += &format!(" (was {old_name})");
+    }
+    ui.label(RichText::new(name).color(color));
+    ui.label(RichText::new(format!("{:?}", row.status)).color(color));
+
+    for col in view.diff.columns.iter() {
+        if let Some(change) = row.category(&col.id) {
+            ui.label(change.old.as_deref().unwrap_or("–"));
+            ui.label(change.new.as_deref().unwrap_or("–"));
+            ui.label("");
+            continue;
         }
+        match row.delta(&col.id) {
+            Some(delta) => {
+                ui.label(format_float(delta.old, Some(&col.unit), true));
+                ui.label(format_float(delta.new, Some(&col.unit), true));
+                let sign = if delta.delta() > 0.0 { "+" } else { "" };
+                ui.label(
+                    RichText::new(sign.to_owned() + &format_float(delta.delta(), None, true))
+                        .strong(),
+                );
+            }
+            None => {
+                ui.label("");
+                ui.label("");
+                ui.label("");
+            }
+        }
+    }
+    ui.end_row();
+
+    for child in row.children.iter() {
+        show_diff_row(ui, view, child, depth + 1);
     }
 }
 
-// ----------------------------------------------------------------------------
+// Drops the root and all rows whose ancestor is selected as well, sorted in tree order
+fn selection_roots(selected_rows: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    // The root is the ancestor of every other row
+    let rows: Vec<&Vec<usize>> = (selected_rows.iter())
+        .filter(|path| !path.is_empty())
+        .collect();
+    let mut roots: Vec<Vec<usize>> = rows
+        .iter()
+        .filter(|path| {
+            !rows
+                .iter()
+                .any(|other| other.len() < path.len() && path.starts_with(other))
+        })
+        .map(|path| (*path).clone())
+        .collect();
+    roots.sort();
+    roots.dedup();
+    roots
+}
 
-/// We derive Deserialize/Serialize so we can persist app state on shutdown.
+#[allow(dead_code)]
 #[derive(serde::Deserialize, serde::Serialize)]
+pub enum DataElement {
+    Number(f32),
+    String(String),
+}
+
+/// An amount in a unit, see `units`, or in a currency of the exchange rates.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct Quantity {
+    pub(crate) amount: f64,
+    pub(crate) unit: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
-pub struct TreeTablesApp {
-    #[serde(skip)]
-    tree_table: TreeTable,
+pub struct RowData {
+    // Empty in files saved before rows had IDs, see `RowData::assign_missing_ids`
+    #[serde(default)]
+    pub(crate) id: RowID,
+    pub(crate) name: String,
 
-    #[serde(skip)]
-    filename: String,
+    // data_elements: HashMap<ColumnID, DataElement>,
+    // Sorted by the column IDs, so that saving the same table gives the same file
+    pub(crate) col_data: BTreeMap<ColumnID, f64>,
+    pub(crate) children: Vec<RowData>,
 
-    #[serde(skip)]
-    file_modified: bool,
+    pub(crate) enabled: bool,
 
-    #[serde(skip)]
-    edit_title_text: bool,
+    pub(crate) multiplier: f64,
 
-    #[serde(skip)] // TODO: Implement serialization
-    save_shortcut: Shortcut,
+    // The parameter which sets the multiplier, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) multiplier_parameter: Option<String>,
 
+    // Values of leaves which were entered in another unit than the unit of their column
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) quantities: BTreeMap<ColumnID, Quantity>,
+
+    // The values of leaves in Boolean and Enum columns, checked Boolean cells hold "true"
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) categories: BTreeMap<ColumnID, String>,
+
+    // The number of enabled leaves per value in Boolean and Enum columns, only for parents
     #[serde(skip)]
-    edit_column_idx: Option<usize>,
+    counts: BTreeMap<ColumnID, BTreeMap<String, usize>>,
 
+    // The units of the amounts in the subtree which are left out of the sums, because they
+    // can't be converted to the unit of their column, e.g. a currency without exchange rate
     #[serde(skip)]
-    close_requested: bool,
+    unconverted: BTreeMap<ColumnID, BTreeSet<String>>,
 
-    show_decimals: bool,
-}
+    // Free text in Markdown, shown as a tooltip of the row name
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub(crate) notes: String,
 
-impl Default for ColumnConfig {
-    fn default() -> ColumnConfig {
-        ColumnConfig {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) tags: Vec<String>,
+
+    // Paths of local files or URLs, e.g. supplier quotes or datasheets
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) links: Vec<String>,
+
+    // The template of the library which the subtree is copied from, whenever the template changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) template_id: Option<String>,
+
+    // The input value and the result after every step of the markup columns
+    #[serde(skip)]
+    markup_results: BTreeMap<ColumnID, Vec<f64>>,
+
+    // Manual values which replace the calculated values of parent rows and derived columns
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) pinned: BTreeMap<ColumnID, f64>,
+
+    // The calculated values of the pinned columns
+    #[serde(skip)]
+    calculated: BTreeMap<ColumnID, f64>,
+
+    // UI State
+    #[serde(skip)] // The expansion is a view preference, see `TreeTablesApp::expanded_rows`
+    expanded: bool,
+    #[serde(skip)]
+    edit_name: bool,
+
+    // The aggregated values of this row need to be recalculated
+    #[serde(skip)]
+    dirty: bool,
+
+    // The values were recalculated since the shares of the row were, see `update_shares`
+    #[serde(skip)]
+    shares_outdated: bool,
+
+    // The weight which the shares of the row were calculated with
+    #[serde(skip)]
+    share_weight: f64,
+}
+
+impl Default for RowData {
+    fn default() -> Self {
+        Self {
             id: Uuid::new_v4().to_string(),
-            caption: "".to_owned(),
-            unit: "€".to_owned(),
-            col_type: ColumnType::Number,
+            name: "".to_owned(),
+            col_data: BTreeMap::new(),
+            children: vec![],
+            edit_name: false,
+            expanded: true,
+            enabled: true,
+            multiplier: 1.0,
+            multiplier_parameter: None,
+            quantities: BTreeMap::new(),
+            categories: BTreeMap::new(),
+            counts: BTreeMap::new(),
+            unconverted: BTreeMap::new(),
+            markup_results: BTreeMap::new(),
+            notes: "".to_owned(),
+            tags: vec![],
+            links: vec![],
+            template_id: None,
+            pinned: BTreeMap::new(),
+            calculated: BTreeMap::new(),
+            dirty: true,
+            shares_outdated: true,
+            share_weight: 0.0,
+        }
+    }
+}
+
+impl RowData {
+    /// Recalculates the aggregated values of all dirty rows.
+    ///
+    /// Branches without changes are skipped, their values are still up to date.
+    // Quantity model: the values of a row are those of a single unit, its multiplier is the
+    // quantity. A parent sums up the values of its enabled children times their multipliers for
+    // every column type, so a multiplier scales the whole subtree. Derived columns of leaves are
+    // calculated from the other values of the row. `column_configs` is in calculation order.
+    fn update(
+        &mut self,
+        column_configs: &[&ColumnConfig],
+        parameters: &[Parameter],
+        exchange_rates: &[ExchangeRate],
+    ) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        self.shares_outdated = true;
+
+        if let Some(name) = &self.multiplier_parameter {
+            self.multiplier = parameter_value(parameters, name);
+        }
+
+        // Update all children
+        for child in self.children.iter_mut() {
+            child.update(column_configs, parameters, exchange_rates);
+        }
+
+        // Update the actual values
+        self.unconverted.clear();
+        for col_cfg in column_configs.iter() {
+            let col_id = &col_cfg.id;
+            let unconverted: BTreeSet<String> = (self.children.iter())
+                .filter(|child| child.enabled)
+                .filter_map(|child| child.unconverted.get(col_id))
+                .flatten()
+                .cloned()
+                .collect();
+            if !unconverted.is_empty() {
+                self.unconverted.insert(col_id.clone(), unconverted);
+            }
+
+            let value = |col_id: &ColumnID| *self.col_data.get(col_id).unwrap_or(&0.0);
+            // Input columns of incompatible units are used as they are, the column header shows a
+            // warning. Amounts which can't be converted are left out.
+            let in_column_unit =
+                |unit: &str| conversion_factor(unit, &col_cfg.unit, exchange_rates);
+            let input_unit = |col_id: &ColumnID| {
+                (column_configs.iter())
+                    .find(|cfg| cfg.id == *col_id)
+                    .and_then(|cfg| in_column_unit(&cfg.unit))
+                    .unwrap_or(1.0)
+            };
+            // The derived columns of a parent sum up its children, an input that differs from
+            // the sum of the children (e.g. pinned) changes them by the difference
+            let children_sum = |col_id: &ColumnID| -> f64 {
+                (self.children.iter())
+                    .filter(|child| child.enabled)
+                    .map(|child| child.multiplier * child.col_data.get(col_id).unwrap_or(&0.0))
+                    .sum()
+            };
+            let input_delta = |col_id: &ColumnID| value(col_id) - children_sum(col_id);
+
+            let calculated = match &col_cfg.col_type {
+                ColumnType::Text | ColumnType::Gantt(_, _) => continue,
+                ColumnType::ShareOfParent(_) | ColumnType::ShareOfTotal(_) => continue,
+                ColumnType::Date(_) if self.children.is_empty() => continue,
+                _ if col_cfg.col_type.is_categorical() && self.children.is_empty() => continue,
+                ColumnType::Boolean | ColumnType::Enum(_) => {
+                    let counts = self.count_categories(col_id);
+                    let checked = *counts.get("true").unwrap_or(&0);
+                    self.counts.insert(col_id.clone(), counts);
+                    match col_cfg.col_type {
+                        ColumnType::Boolean => checked as f64,
+                        _ => continue,
+                    }
+                }
+                ColumnType::Date(rollup) => {
+                    let dates = (self.children.iter())
+                        .filter(|child| child.enabled)
+                        .filter_map(|child| child.col_data.get(col_id).copied());
+                    let date = match rollup {
+                        DateRollup::Earliest => dates.min_by(f64::total_cmp),
+                        DateRollup::Latest => dates.max_by(f64::total_cmp),
+                    };
+                    let Some(date) = date else {
+                        self.col_data.remove(col_id);
+                        continue;
+                    };
+                    date
+                }
+                ColumnType::Markup(input_col_id, steps) if !self.children.is_empty() => {
+                    let mut results = vec![0.0; steps.len() + 1];
+                    for child in self.children.iter().filter(|child| child.enabled) {
+                        let child_results = child.markup_results.get(col_id).into_iter().flatten();
+                        for (sum, result) in results.iter_mut().zip(child_results) {
+                            *sum += child.multiplier * result;
+                        }
+                    }
+                    // Every step is affine, so the difference passes through the percentages only
+                    let mut delta = input_delta(input_col_id) * input_unit(input_col_id);
+                    results[0] += delta;
+                    for (result, step) in results[1..].iter_mut().zip(steps.iter()) {
+                        delta = step.apply(delta, parameters) - step.apply(0.0, parameters);
+                        *result += delta;
+                    }
+                    self.markup_results.insert(col_id.clone(), results);
+                    children_sum(col_id) + delta
+                }
+                _ if !self.children.is_empty() => {
+                    let sum = children_sum(col_id);
+                    match &col_cfg.col_type {
+                        ColumnType::MultiplyByFactor(input_col_id, factor) => {
+                            sum + input_delta(input_col_id) * factor.resolve(parameters)
+                        }
+                        ColumnType::RowSum(input_col_ids) => {
+                            let deltas = input_col_ids
+                                .iter()
+                                .map(|id| input_delta(id) * input_unit(id));
+                            sum + deltas.sum::<f64>()
+                        }
+                        // Which leaves match is unknown for the difference, the matching share
+                        // stays the same
+                        ColumnType::SumIf(input_col_id, _) => match children_sum(input_col_id) {
+                            0.0 => sum,
+                            children => sum * value(input_col_id) / children,
+                        },
+                        _ => sum,
+                    }
+                }
+                ColumnType::Number | ColumnType::Currency | ColumnType::Duration => {
+                    let Some(quantity) = self.quantities.get(col_id) else {
+                        continue;
+                    };
+                    match in_column_unit(&quantity.unit) {
+                        Some(factor) => quantity.amount * factor,
+                        None => {
+                            let units = BTreeSet::from([quantity.unit.clone()]);
+                            self.unconverted.insert(col_id.clone(), units);
+                            0.0
+                        }
+                    }
+                }
+                ColumnType::MultiplyByFactor(input_col_id, factor) => {
+                    value(input_col_id) * factor.resolve(parameters)
+                }
+                ColumnType::RowSum(input_col_ids) => (input_col_ids.iter())
+                    .map(|col_id| value(col_id) * input_unit(col_id))
+                    .sum(),
+                ColumnType::Markup(input_col_id, steps) => {
+                    let mut results = vec![value(input_col_id) * input_unit(input_col_id)];
+                    for step in steps.iter() {
+                        results.push(step.apply(*results.last().unwrap(), parameters));
+                    }
+                    let result = *results.last().unwrap();
+                    self.markup_results.insert(col_id.clone(), results);
+                    result
+                }
+                ColumnType::SumIf(input_col_id, condition) => match condition.matches(self) {
+                    true => value(input_col_id) * input_unit(input_col_id),
+                    false => 0.0,
+                },
+            };
+            let calculated = self.pinned_or(col_id, calculated);
+            self.col_data.insert(col_id.clone(), calculated);
+        }
+    }
+
+    // Calculates the share columns of the subtree, once the values of all rows are known.
+    // `weight` converts the values of this row to its part of the root row's values, i.e. it's
+    // the product of the multipliers on the path, and 0 below a disabled row. Only the rows whose
+    // values, parent values, totals or weight changed are calculated again.
+    fn update_shares(
+        &mut self,
+        column_configs: &[&ColumnConfig],
+        parent_values: Option<(&BTreeMap<ColumnID, f64>, bool)>,
+        root_values: &BTreeMap<ColumnID, f64>,
+        totals_changed: bool,
+        weight: f64,
+    ) {
+        let parent_changed = parent_values.is_some_and(|(_, changed)| changed);
+        let subtree_changed = self.shares_outdated || totals_changed || weight != self.share_weight;
+        if !subtree_changed && !parent_changed {
+            return;
+        }
+        let parent_values = parent_values.map(|(values, _)| values);
+        let part_of_parent = if self.enabled { self.multiplier } else { 0.0 };
+        for col_cfg in column_configs.iter() {
+            let (input_col_id, totals, weight) = match &col_cfg.col_type {
+                ColumnType::ShareOfParent(input_col_id) => {
+                    (input_col_id, parent_values, part_of_parent)
+                }
+                ColumnType::ShareOfTotal(input_col_id) => (input_col_id, Some(root_values), weight),
+                _ => continue,
+            };
+            // The root row is the whole of itself
+            let share = match totals {
+                Some(totals) => {
+                    let total = *totals.get(input_col_id).unwrap_or(&0.0);
+                    let value = *self.col_data.get(input_col_id).unwrap_or(&0.0);
+                    match total {
+                        0.0 => 0.0,
+                        _ => 100.0 * weight * value / total,
+                    }
+                }
+                None => 100.0,
+            };
+            self.col_data.insert(col_cfg.id.clone(), share);
+        }
+
+        let changed = self.shares_outdated;
+        self.shares_outdated = false;
+        self.share_weight = weight;
+        if !subtree_changed {
+            return;
+        }
+        for child in self.children.iter_mut() {
+            let weight = if child.enabled {
+                weight * child.multiplier
+            } else {
+                0.0
+            };
+            let parent_values = Some((&self.col_data, changed));
+            child.update_shares(
+                column_configs,
+                parent_values,
+                root_values,
+                totals_changed,
+                weight,
+            );
+        }
+    }
+
+    // A new row with the content of the template, which stays linked to it if `linked` is set
+    fn from_template(template: &Template, linked: bool) -> RowData {
+        let mut row = RowData {
+            template_id: Some(template.id.clone()),
+            ..RowData::default()
+        };
+        row.apply_template(&template.row);
+        if !linked {
+            row.template_id = None;
+        }
+        row
+    }
+
+    // Replaces the content of the row by the template, keeping the id, quantity and state of the
+    // row. The rows below get the template's ids prefixed with the row's id, so that they are
+    // unique in the document and stay the same on every update.
+    fn apply_template(&mut self, template: &RowData) {
+        let mut content = template.clone();
+        for child in content.children.iter_mut() {
+            child.prefix_ids(&format!("{}/", self.id));
+        }
+        content.id = self.id.clone();
+        content.enabled = self.enabled;
+        content.multiplier = self.multiplier;
+        content.multiplier_parameter = self.multiplier_parameter.clone();
+        content.expanded = self.expanded;
+        content.template_id = self.template_id.clone();
+        content.mark_all_dirty();
+        *self = content;
+    }
+
+    fn prefix_ids(&mut self, prefix: &str) {
+        self.id = format!("{prefix}{}", self.id);
+        for child in self.children.iter_mut() {
+            child.prefix_ids(prefix);
+        }
+    }
+
+    // The content of the row as a template: a single, unlinked unit with the template's own ids.
+    // Only the values of the editable columns are kept, the others depend on the document.
+    fn to_template(&self, editable: &[&ColumnID]) -> RowData {
+        let mut content = self.clone();
+        content.unlink_templates();
+        content.keep_inputs(editable);
+        let prefix = format!("{}/", self.id);
+        for child in content.children.iter_mut() {
+            child.strip_id_prefix(&prefix);
+        }
+        content.enabled = true;
+        content.multiplier = 1.0;
+        content.multiplier_parameter = None;
+        content
+    }
+
+    fn keep_inputs(&mut self, editable: &[&ColumnID]) {
+        match self.children.is_empty() {
+            true => self.col_data.retain(|col_id, _| editable.contains(&col_id)),
+            false => self.col_data.clear(),
+        }
+        for child in self.children.iter_mut() {
+            child.keep_inputs(editable);
+        }
+    }
+
+    fn strip_id_prefix(&mut self, prefix: &str) {
+        if let Some(id) = self.id.strip_prefix(prefix) {
+            self.id = id.to_owned();
+        }
+        for child in self.children.iter_mut() {
+            child.strip_id_prefix(prefix);
+        }
+    }
+
+    fn unlink_templates(&mut self) {
+        self.template_id = None;
+        for child in self.children.iter_mut() {
+            child.unlink_templates();
+        }
+    }
+
+    fn matches_template(&self, template: &Template, editable: &[&ColumnID]) -> bool {
+        let mut content = self.to_template(editable);
+        content.id = template.row.id.clone();
+        serde_json::to_value(&content).ok() == serde_json::to_value(&template.row).ok()
+    }
+
+    // Updates the rows which are linked to a template of the library, returns whether any row
+    // changed. Rows whose template was removed keep their content.
+    fn apply_templates(&mut self, library: &Library, editable: &[&ColumnID]) -> bool {
+        let template = (self.template_id.as_ref()).and_then(|id| library.find(id));
+        if let Some(template) = template {
+            if self.matches_template(template, editable) {
+                return false;
+            }
+            self.apply_template(&template.row);
+            return true;
+        }
+        let mut changed = false;
+        for child in self.children.iter_mut() {
+            changed |= child.apply_templates(library, editable);
+        }
+        changed
+    }
+
+    // The ID and name of every linked row which differs from its template
+    fn outdated_templates(
+        &self,
+        library: &Library,
+        editable: &[&ColumnID],
+        outdated: &mut Vec<(RowID, String)>,
+    ) {
+        let template = (self.template_id.as_ref()).and_then(|id| library.find(id));
+        if let Some(template) = template {
+            if !self.matches_template(template, editable) {
+                outdated.push((self.id.clone(), self.name.clone()));
+            }
+            return;
+        }
+        for child in self.children.iter() {
+            child.outdated_templates(library, editable, outdated);
+        }
+    }
+
+    // The tags of the subtree, to filter by
+    fn all_tags(&self) -> BTreeSet<String> {
+        let mut tags: BTreeSet<String> = self.tags.iter().cloned().collect();
+        for child in self.children.iter() {
+            tags.extend(child.all_tags());
+        }
+        tags
+    }
+
+    // Counts the enabled leaves per category, leaves without a value count as ""
+    fn count_categories(&self, col_id: &ColumnID) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for child in self.children.iter().filter(|child| child.enabled) {
+            if child.children.is_empty() {
+                let value = child.categories.get(col_id).cloned().unwrap_or_default();
+                *counts.entry(value).or_insert(0) += 1;
+            } else if let Some(child_counts) = child.counts.get(col_id) {
+                for (value, count) in child_counts.iter() {
+                    *counts.entry(value.clone()).or_insert(0) += count;
+                }
+            }
+        }
+        counts
+    }
+
+    // Converts the value of a leaf to the given unit, in which it is edited from now on
+    fn set_unit(
+        &mut self,
+        col_id: &ColumnID,
+        column_unit: &str,
+        unit: &str,
+        exchange_rates: &[ExchangeRate],
+    ) {
+        if unit == column_unit {
+            self.quantities.remove(col_id);
+        } else if let Some(factor) = conversion_factor(column_unit, unit, exchange_rates) {
+            let quantity = Quantity {
+                amount: *self.col_data.get(col_id).unwrap_or(&0.0) * factor,
+                unit: unit.to_owned(),
+            };
+            self.quantities.insert(col_id.clone(), quantity);
+        }
+    }
+
+    // The pinned value of the column if there is one, otherwise the calculated value
+    fn pinned_or(&mut self, col_id: &ColumnID, calculated: f64) -> f64 {
+        match self.pinned.get(col_id) {
+            Some(pinned) => {
+                self.calculated.insert(col_id.clone(), calculated);
+                *pinned
+            }
+            None => calculated,
+        }
+    }
+
+    // Marks the row at the given path and all its ancestors for recalculation
+    fn mark_dirty(&mut self, row_path: &[usize]) {
+        self.dirty = true;
+        if let Some((idx, rest)) = row_path.split_first() {
+            if let Some(child) = self.children.get_mut(*idx) {
+                child.mark_dirty(rest);
+            }
+        }
+    }
+
+    // The names of the rows whose multiplier is set by the parameter
+    fn collect_parameter_users(&self, name: &str, users: &mut Vec<String>) {
+        if self.multiplier_parameter.as_deref() == Some(name) {
+            users.push(self.name.clone());
+        }
+        for child in self.children.iter() {
+            child.collect_parameter_users(name, users);
+        }
+    }
+
+    // Gives the rows without ID one derived from their path, e.g. `row/0/2`, so that they get
+    // the same IDs every time the file is loaded
+    fn assign_missing_ids(&mut self, path_id: &str) {
+        if self.id.is_empty() {
+            self.id = path_id.to_owned();
+        }
+        for (idx, child) in self.children.iter_mut().enumerate() {
+            child.assign_missing_ids(&format!("{path_id}/{idx}"));
+        }
+    }
+
+    fn rename_multiplier_parameter(&mut self, old_name: &str, new_name: &str) {
+        if self.multiplier_parameter.as_deref() == Some(old_name) {
+            self.multiplier_parameter = Some(new_name.to_owned());
+        }
+        for child in self.children.iter_mut() {
+            child.rename_multiplier_parameter(old_name, new_name);
+        }
+    }
+
+    pub(crate) fn mark_all_dirty(&mut self) {
+        self.dirty = true;
+        for child in self.children.iter_mut() {
+            child.mark_all_dirty();
+        }
+    }
+
+    fn add_child(&mut self, column_configs: &[ColumnConfig]) {
+        let mut new_col_data = BTreeMap::new();
+        for col_cfg in column_configs.iter() {
+            if self.children.is_empty() {
+                new_col_data.insert(
+                    col_cfg.id.clone(),
+                    *self.col_data.get(&col_cfg.id).unwrap_or(&0.0),
+                );
+            } else {
+                new_col_data.insert(col_cfg.id.clone(), 0.0);
+            }
+        }
+        self.children.push(RowData {
+            col_data: new_col_data,
+            expanded: false,
+            edit_name: true,
+            ..RowData::default()
+        });
+    }
+
+    fn get_row(&self, row_path: &[usize]) -> Option<&RowData> {
+        match row_path.split_first() {
+            Some((idx, rest)) => self.children.get(*idx)?.get_row(rest),
+            None => Some(self),
+        }
+    }
+
+    pub(crate) fn get_row_mut(&mut self, row_path: &[usize]) -> Option<&mut RowData> {
+        match row_path.split_first() {
+            Some((idx, rest)) => self.children.get_mut(*idx)?.get_row_mut(rest),
+            None => Some(self),
+        }
+    }
+
+    // Removes the row at the given path. The row itself (empty path) can't be removed.
+    pub(crate) fn remove_row(&mut self, row_path: &[usize]) -> Option<RowData> {
+        let (idx, parent_path) = row_path.split_last()?;
+        let parent = self.get_row_mut(parent_path)?;
+        if *idx < parent.children.len() {
+            Some(parent.children.remove(*idx))
+        } else {
+            None
+        }
+    }
+
+    // Paths of all rows in the order they are shown, skipping collapsed subtrees
+    fn visible_paths(&self, filter: Option<&RowFilter>) -> Vec<Vec<usize>> {
+        let mut paths = vec![];
+        self.collect_paths(&mut vec![], &mut paths, true, filter);
+        paths
+    }
+
+    // Paths of all rows in tree order, including the collapsed ones
+    fn all_paths(&self) -> Vec<Vec<usize>> {
+        let mut paths = vec![];
+        self.collect_paths(&mut vec![], &mut paths, false, None);
+        paths
+    }
+
+    fn collect_paths(
+        &self,
+        row_path: &mut Vec<usize>,
+        paths: &mut Vec<Vec<usize>>,
+        visible_only: bool,
+        filter: Option<&RowFilter>,
+    ) {
+        paths.push(row_path.clone());
+        if self.expanded || !visible_only {
+            let filter = filter.and_then(|filter| filter.for_children(self));
+            for (i, child) in self.children.iter().enumerate() {
+                if filter.is_none_or(|filter| filter.matches(child)) {
+                    row_path.push(i);
+                    child.collect_paths(row_path, paths, visible_only, filter);
+                    row_path.pop();
+                }
+            }
+        }
+    }
+
+    /// Expands all rows above the given depth and collapses the others, None expands everything.
+    fn expand_to_depth(&mut self, depth: Option<usize>) {
+        self.expanded = depth.is_none_or(|d| d > 0);
+        for child in self.children.iter_mut() {
+            child.expand_to_depth(depth.map(|d| d.saturating_sub(1)));
+        }
+    }
+
+    // Expands all ancestors of the given row, so that it becomes visible
+    fn reveal(&mut self, row_path: &[usize]) {
+        if let Some((idx, rest)) = row_path.split_first() {
+            self.expanded = true;
+            if let Some(child) = self.children.get_mut(*idx) {
+                child.reveal(rest);
+            }
+        }
+    }
+
+    // Path of the first row (in tree order) that fulfills the predicate
+    pub(crate) fn find_path(&self, predicate: &dyn Fn(&RowData) -> bool) -> Option<Vec<usize>> {
+        if predicate(self) {
+            return Some(vec![]);
+        }
+        for (i, child) in self.children.iter().enumerate() {
+            if let Some(mut row_path) = child.find_path(predicate) {
+                row_path.insert(0, i);
+                return Some(row_path);
+            }
+        }
+        None
+    }
+
+    fn collect_expanded_ids(&self, expanded_ids: &mut HashSet<RowID>) {
+        if self.expanded {
+            expanded_ids.insert(self.id.clone());
+        }
+        for child in self.children.iter() {
+            child.collect_expanded_ids(expanded_ids);
+        }
+    }
+
+    fn apply_expanded_ids(&mut self, expanded_ids: &HashSet<RowID>) {
+        self.expanded = expanded_ids.contains(&self.id);
+        for child in self.children.iter_mut() {
+            child.apply_expanded_ids(expanded_ids);
+        }
+    }
+
+    // A copy of the row without its subtree, e.g. for undo steps and merges
+    pub(crate) fn without_children(&self) -> RowData {
+        RowData {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            col_data: self.col_data.clone(),
+            children: vec![],
+            enabled: self.enabled,
+            multiplier: self.multiplier,
+            multiplier_parameter: self.multiplier_parameter.clone(),
+            quantities: self.quantities.clone(),
+            categories: self.categories.clone(),
+            counts: self.counts.clone(),
+            unconverted: self.unconverted.clone(),
+            notes: self.notes.clone(),
+            tags: self.tags.clone(),
+            links: self.links.clone(),
+            template_id: self.template_id.clone(),
+            markup_results: self.markup_results.clone(),
+            pinned: self.pinned.clone(),
+            calculated: self.calculated.clone(),
+            expanded: self.expanded,
+            edit_name: self.edit_name,
+            dirty: self.dirty,
+            shares_outdated: self.shares_outdated,
+            share_weight: self.share_weight,
+        }
+    }
+
+    // Whether the rows are equal apart from their children and the state which is derived or
+    // only concerns the view
+    fn same_content(&self, other: &RowData) -> bool {
+        let RowData {
+            id,
+            name,
+            col_data,
+            children: _,
+            enabled,
+            multiplier,
+            multiplier_parameter,
+            quantities,
+            categories,
+            counts: _,
+            unconverted: _,
+            notes,
+            tags,
+            links,
+            template_id,
+            markup_results: _,
+            pinned,
+            calculated: _,
+            expanded: _,
+            edit_name: _,
+            dirty: _,
+            shares_outdated: _,
+            share_weight: _,
+        } = self;
+        *id == other.id
+            && *name == other.name
+            && *col_data == other.col_data
+            && *enabled == other.enabled
+            && *multiplier == other.multiplier
+            && *multiplier_parameter == other.multiplier_parameter
+            && *quantities == other.quantities
+            && *categories == other.categories
+            && *notes == other.notes
+            && *tags == other.tags
+            && *links == other.links
+            && *template_id == other.template_id
+            && *pinned == other.pinned
+    }
+
+    // The values of all rows which a scenario can override, the editable values only of leaves
+    fn collect_values(&self, editable: &[&ColumnID], values: &mut BTreeMap<RowID, RowValues>) {
+        let mut row_values = RowValues {
+            enabled: Some(self.enabled),
+            multiplier: Some(self.multiplier),
+            ..RowValues::default()
+        };
+        if self.children.is_empty() {
+            for col_id in editable.iter() {
+                if let Some(value) = self.col_data.get(*col_id) {
+                    row_values.col_data.insert((*col_id).clone(), *value);
+                }
+                if let Some(quantity) = self.quantities.get(*col_id) {
+                    (row_values.quantities).insert((*col_id).clone(), Some(quantity.clone()));
+                }
+                if let Some(category) = self.categories.get(*col_id) {
+                    (row_values.categories).insert((*col_id).clone(), Some(category.clone()));
+                }
+            }
+        }
+        values.insert(self.id.clone(), row_values);
+        for child in self.children.iter() {
+            child.collect_values(editable, values);
+        }
+    }
+
+    fn apply_overrides(&mut self, overrides: &BTreeMap<RowID, RowValues>) {
+        if let Some(values) = overrides.get(&self.id) {
+            self.enabled = values.enabled.unwrap_or(self.enabled);
+            self.multiplier = values.multiplier.unwrap_or(self.multiplier);
+            if self.children.is_empty() {
+                self.col_data.extend(values.col_data.clone());
+                apply_entries(&mut self.quantities, &values.quantities);
+                apply_entries(&mut self.categories, &values.categories);
+            }
+        }
+        for child in self.children.iter_mut() {
+            child.apply_overrides(overrides);
+        }
+    }
+
+    // Collects the values which differ from the base values as overrides and restores the base.
+    // Rows without base values were added in the scenario, they keep their values.
+    fn restore_base_values(
+        &mut self,
+        base: &BTreeMap<RowID, RowValues>,
+        editable: &[&ColumnID],
+        overrides: &mut BTreeMap<RowID, RowValues>,
+    ) {
+        if let Some(base_values) = base.get(&self.id) {
+            let mut changed = RowValues::default();
+            if base_values.enabled != Some(self.enabled) {
+                changed.enabled = Some(self.enabled);
+                self.enabled = base_values.enabled.unwrap_or(true);
+            }
+            if base_values.multiplier != Some(self.multiplier) {
+                changed.multiplier = Some(self.multiplier);
+                self.multiplier = base_values.multiplier.unwrap_or(1.0);
+            }
+            if self.children.is_empty() {
+                for col_id in editable.iter() {
+                    let base_value = base_values.col_data.get(*col_id);
+                    if self.col_data.get(*col_id) == base_value {
+                        continue;
+                    }
+                    let value = *self.col_data.get(*col_id).unwrap_or(&0.0);
+                    changed.col_data.insert((*col_id).clone(), value);
+                    match base_value {
+                        Some(base_value) => self.col_data.insert((*col_id).clone(), *base_value),
+                        None => self.col_data.remove(*col_id),
+                    };
+                }
+                changed.quantities =
+                    restore_entries(&mut self.quantities, &base_values.quantities, editable);
+                changed.categories =
+                    restore_entries(&mut self.categories, &base_values.categories, editable);
+            }
+            if changed != RowValues::default() {
+                overrides.insert(self.id.clone(), changed);
+            }
+        }
+        for child in self.children.iter_mut() {
+            child.restore_base_values(base, editable, overrides);
+        }
+    }
+
+    // The lines of the table in the order they are shown, skipping collapsed subtrees
+    fn table_lines(&self, filter: Option<&RowFilter>) -> Vec<TableLine> {
+        let mut lines = vec![];
+        self.collect_table_lines(&mut vec![], true, filter, &mut lines);
+        lines
+    }
+
+    fn collect_table_lines(
+        &self,
+        row_path: &mut Vec<usize>,
+        parent_enabled: bool,
+        filter: Option<&RowFilter>,
+        lines: &mut Vec<TableLine>,
+    ) {
+        lines.push(TableLine::Row {
+            row_path: row_path.clone(),
+            parent_enabled,
+        });
+        if self.expanded {
+            let enabled = parent_enabled && self.enabled;
+            let filter = filter.and_then(|filter| filter.for_children(self));
+            for (i, child) in self.children.iter().enumerate() {
+                if filter.is_none_or(|filter| filter.matches(child)) {
+                    row_path.push(i);
+                    child.collect_table_lines(row_path, enabled, filter, lines);
+                    row_path.pop();
+                }
+            }
+
+            // Button to add a new element at the same level
+            lines.push(TableLine::AddChild {
+                row_path: row_path.clone(),
+            });
+        }
+    }
+
+    // Renders the expand button, the enabled checkbox, the multiplier and the name of the row
+    fn render_name(
+        &mut self,
+        ui: &mut Ui,
+        parent_enabled: bool,
+        show_decimals: bool,
+        row_path: &[usize],
+        nav: &mut TableNavigation,
+    ) -> Option<Action> {
+        let mut action = None;
+
+        ui.add_space(INDENT_WIDTH * row_path.len() as f32);
+        if ui.expand_button(&mut self.expanded).changed() {
+            action = Some(Action::Expanded);
+        }
+        if !row_path.is_empty() {
+            if ui.checkbox(&mut self.enabled, "").changed() {
+                action = Some(Action::Modified);
+            }
+
+            let resp = ui.add_enabled(
+                self.enabled && parent_enabled && self.multiplier_parameter.is_none(),
+                egui::DragValue::new(&mut self.multiplier)
+                    .speed(1.0)
+                    .suffix("x")
+                    .custom_formatter(|n, _| format_float(n, None, show_decimals))
+                    .custom_parser(parse_float),
+            );
+            let resp = match &self.multiplier_parameter {
+                Some(name) => resp.on_disabled_hover_text(format!("Set by the parameter {name}")),
+                None => resp,
+            };
+            if resp.changed() {
+                action = Some(Action::Modified);
+            }
+        }
+        if nav.is_editing(row_path, 0) {
+            let resp = ui.text_edit_singleline(nav.edit_buffer.as_mut().unwrap());
+            resp.request_focus();
+        } else if self.edit_name {
+            if ui.text_edit_singleline(&mut self.name).lost_focus() && !self.name.is_empty() {
+                self.edit_name = false;
+                action = Some(Action::Modified);
+            }
+        } else {
+            let resp = ui.selectable_label(nav.is_selected(row_path), self.name.clone() + ":");
+            if resp.double_clicked() {
+                self.edit_name = true;
+            } else if resp.clicked() {
+                nav.set_cursor(row_path, 0);
+                nav.clicked_row = Some((row_path.to_vec(), ui.input(|i| i.modifiers)));
+            }
+            if nav.is_cursor(row_path, 0) {
+                highlight_cursor(ui, &resp);
+            }
+            resp.context_menu(|ui| {
+                if ui.button("Notes, tags and links…").clicked() {
+                    nav.details_row = Some(row_path.to_vec());
+                    ui.close_menu();
+                }
+            });
+
+            if self.template_id.is_some() {
+                ui.label("🧩")
+                    .on_hover_text("Linked to a template of the library");
+            }
+            if !self.notes.is_empty() {
+                ui.label("📝").on_hover_text(&self.notes);
+            }
+            if !self.tags.is_empty() {
+                let tags: Vec<String> = self.tags.iter().map(|tag| format!("#{tag}")).collect();
+                ui.weak(tags.join(" "));
+            }
+            if !self.links.is_empty() {
+                ui.menu_button("🔗", |ui| {
+                    for link in self.links.iter() {
+                        if ui.link(link).clicked() {
+                            ui.ctx().open_url(egui::OpenUrl::new_tab(link_url(link)));
+                            ui.close_menu();
+                        }
+                    }
+                });
+            }
+        }
+
+        action
+    }
+
+    // Renders the value of the given column, `col_idx` is the index into the column configs
+    #[allow(clippy::too_many_arguments)]
+    fn render_cell(
+        &mut self,
+        ui: &mut Ui,
+        col_idx: usize,
+        col_cfg: &ColumnConfig,
+        parent_enabled: bool,
+        show_decimals: bool,
+        row_path: &[usize],
+        nav: &mut TableNavigation,
+        exchange_rates: &[ExchangeRate],
+        root_values: &BTreeMap<ColumnID, f64>,
+    ) -> Option<Action> {
+        let mut action = None;
+
+        let col_id = &col_cfg.id;
+        // Cells without a value aren't stored, so that just viewing doesn't change the document
+        let mut value = *self.col_data.get(col_id).unwrap_or(&0.0);
+        let unit = col_cfg.unit.as_str();
+
+        let editable = self.children.is_empty() && col_cfg.col_type.is_editable();
+        let pinned = !editable && self.pinned.contains_key(col_id);
+
+        let resp = if nav.is_editing(row_path, col_idx + 1) {
+            let resp = ui.add(
+                egui::TextEdit::singleline(nav.edit_buffer.as_mut().unwrap())
+                    .desired_width(col_cfg.width),
+            );
+            resp.request_focus();
+            resp
+        } else if pinned {
+            // Pinned dates, e.g. of a parent row, are edited as dates
+            let is_date = matches!(col_cfg.col_type, ColumnType::Date(_));
+            let format = |n: f64| match is_date {
+                true => format_date(n),
+                false => format_float(n, Some(unit), show_decimals),
+            };
+            let calculated = *self.calculated.get(col_id).unwrap_or(&value);
+            let diverges = (calculated - value).abs() > 1e-9;
+            let drag_value =
+                egui::DragValue::new(&mut value).prefix(if diverges { "⚠ " } else { "📌 " });
+            let drag_value = match is_date {
+                true => drag_value
+                    .speed(0.2)
+                    .custom_formatter(|n, _| format_date(n))
+                    .custom_parser(parse_date),
+                false => drag_value
+                    .speed(1.0)
+                    .custom_formatter(|n, _| format_float(n, None, show_decimals))
+                    .suffix(format!(" {unit}"))
+                    .custom_parser(parse_float),
+            };
+            let resp = ui
+                .add_enabled(self.enabled && parent_enabled, drag_value)
+                .on_hover_text(format!("Pinned, calculated: {}", format(calculated)));
+            if resp.changed() {
+                let value = if is_date { value.floor() } else { value };
+                self.pinned.insert(col_id.clone(), value);
+                action = Some(Action::Modified);
+            }
+            resp
+        } else if let ColumnType::Gantt(start_col_id, end_col_id) = &col_cfg.col_type {
+            let dates = |values: &BTreeMap<ColumnID, f64>| {
+                Some((*values.get(start_col_id)?, *values.get(end_col_id)?))
+            };
+            let enabled = self.enabled && parent_enabled;
+            gantt_bar(
+                ui,
+                col_cfg.width,
+                enabled,
+                dates(&self.col_data),
+                dates(root_values),
+            )
+        } else if matches!(col_cfg.col_type, ColumnType::Date(_)) {
+            let enabled = self.enabled && parent_enabled;
+            match self.col_data.get(col_id) {
+                Some(_) if editable => {
+                    let resp = ui.add_enabled(
+                        enabled,
+                        egui::DragValue::new(&mut value)
+                            .speed(0.2)
+                            .custom_formatter(|n, _| format_date(n))
+                            .custom_parser(parse_date),
+                    );
+                    if resp.changed() {
+                        self.col_data.insert(col_id.clone(), value.floor());
+                        action = Some(Action::Modified);
+                    }
+                    resp
+                }
+                None if editable => {
+                    let resp = ui
+                        .add_enabled(enabled, egui::Button::new("–"))
+                        .on_hover_text("Set to today");
+                    if resp.clicked() {
+                        self.col_data.insert(col_id.clone(), dates::today());
+                        action = Some(Action::Modified);
+                    }
+                    resp
+                }
+                date => ui.add(
+                    egui::Label::new(date.map_or("–".to_owned(), |date| format_date(*date)))
+                        .sense(Sense::click()),
+                ),
+            }
+        } else if col_cfg.col_type.is_categorical() && !editable {
+            let counts = self.counts.get(col_id).cloned().unwrap_or_default();
+            let text = match &col_cfg.col_type {
+                ColumnType::Boolean => {
+                    let total: usize = counts.values().sum();
+                    format!("{} of {total}", counts.get("true").unwrap_or(&0))
+                }
+                _ => (counts.iter())
+                    .filter(|(value, _)| !value.is_empty())
+                    .map(|(value, count)| format!("{count} {value}"))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
+            ui.add(
+                egui::Label::new(text.clone())
+                    .truncate(true)
+                    .sense(Sense::click()),
+            )
+            .on_hover_text(text)
+        } else if col_cfg.col_type == ColumnType::Boolean {
+            let mut checked = self.categories.contains_key(col_id);
+            let resp = ui.add_enabled(
+                self.enabled && parent_enabled,
+                egui::Checkbox::without_text(&mut checked),
+            );
+            if resp.changed() {
+                match checked {
+                    true => self.categories.insert(col_id.clone(), "true".to_owned()),
+                    false => self.categories.remove(col_id),
+                };
+                action = Some(Action::Modified);
+            }
+            resp
+        } else if let ColumnType::Enum(values) = &col_cfg.col_type {
+            let mut selected = self.categories.get(col_id).cloned();
+            let resp = ui
+                .add_enabled_ui(self.enabled && parent_enabled, |ui| {
+                    egui::ComboBox::from_id_source((&self.id, col_id))
+                        .width(col_cfg.width - 20.0)
+                        .selected_text(selected.clone().unwrap_or("–".to_owned()))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut selected, None, "–");
+                            for value in values.iter() {
+                                ui.selectable_value(&mut selected, Some(value.clone()), value);
+                            }
+                        })
+                        .response
+                })
+                .inner;
+            if selected.as_ref() != self.categories.get(col_id) {
+                match selected {
+                    Some(value) => self.categories.insert(col_id.clone(), value),
+                    None => self.categories.remove(col_id),
+                };
+                action = Some(Action::Modified);
+            }
+            resp
+        } else if col_cfg.col_type.is_share() {
+            ui.add(egui::Label::new(format_float(value, Some("%"), true)).sense(Sense::click()))
+        } else if let Some(quantity) = self.quantities.get_mut(col_id).filter(|_| editable) {
+            let converted = conversion_factor(&quantity.unit, unit, exchange_rates);
+            let resp = ui
+                .add_enabled(
+                    self.enabled && parent_enabled,
+                    egui::DragValue::new(&mut quantity.amount)
+                        .speed(1.0)
+                        .prefix(if converted.is_none() { "⚠ " } else { "" })
+                        .suffix(format!(" {}", quantity.unit))
+                        .custom_formatter(|n, _| format_float(n, None, show_decimals))
+                        .custom_parser(parse_float),
+                )
+                .on_hover_text(match converted {
+                    Some(_) => format!("= {}", format_float(value, Some(unit), show_decimals)),
+                    None => format!("{} can't be converted to {unit}", quantity.unit),
+                });
+            if resp.changed() {
+                action = Some(Action::Modified);
+            }
+            resp
+        } else if editable {
+            let resp = ui.add_enabled(
+                self.enabled && parent_enabled,
+                egui::DragValue::new(&mut value)
+                    .speed(1.0)
+                    .suffix(format!(" {unit}"))
+                    .custom_formatter(|n, _| format_float(n, None, show_decimals))
+                    .custom_parser(parse_float),
+            );
+            if resp.changed() {
+                self.col_data.insert(col_id.clone(), value);
+                action = Some(Action::Modified);
+            }
+            resp
+        } else if let ColumnType::Markup(_, steps) = &col_cfg.col_type {
+            let resp = ui.add(
+                egui::Label::new(format_float(value, Some(unit), show_decimals))
+                    .sense(Sense::click()),
+            );
+            match self.markup_results.get(col_id) {
+                Some(results) => {
+                    resp.on_hover_text(markup_breakdown(steps, results, unit, show_decimals))
+                }
+                None => resp,
+            }
+        } else {
+            ui.add(
+                egui::Label::new(format_float(value, Some(unit), show_decimals))
+                    .sense(Sense::click()),
+            )
+        };
+
+        // Values can be entered in any unit which converts to the unit of the column
+        let entry_units = col_cfg.entry_units(exchange_rates);
+        if editable && entry_units.len() > 1 {
+            resp.context_menu(|ui| {
+                ui.label("Enter in");
+                for other_unit in entry_units {
+                    if ui.button(&other_unit).clicked() {
+                        self.set_unit(col_id, unit, &other_unit, exchange_rates);
+                        action = Some(Action::Modified);
+                        ui.close_menu();
+                    }
+                }
+            });
+        }
+
+        // Calculated values can be replaced by a manual value
+        let has_value = !matches!(col_cfg.col_type, ColumnType::Text | ColumnType::Gantt(_, _))
+            && !col_cfg.col_type.is_categorical()
+            && !col_cfg.col_type.is_share();
+        if !editable && has_value {
+            resp.context_menu(|ui| {
+                if pinned && ui.button("Unpin value").clicked() {
+                    self.pinned.remove(col_id);
+                    self.calculated.remove(col_id);
+                    action = Some(Action::Modified);
+                    ui.close_menu();
+                }
+                if !pinned && ui.button("Pin value").clicked() {
+                    self.pinned.insert(col_id.clone(), value);
+                    action = Some(Action::Modified);
+                    ui.close_menu();
+                }
+            });
+        }
+
+        if resp.clicked() && !nav.is_editing(row_path, col_idx + 1) {
+            nav.set_cursor(row_path, col_idx + 1);
+        }
+        if nav.is_cursor(row_path, col_idx + 1) {
+            highlight_cursor(ui, &resp);
+        }
+
+        action
+    }
+}
+
+// Local paths are opened as file URLs
+fn link_url(link: &str) -> String {
+    match link.contains("://") {
+        true => link.to_owned(),
+        false => format!("file://{}", link.trim()),
+    }
+}
+
+// Edits a list of texts, returns whether it changed. `editing` is set while an item has the focus.
+fn edit_list(ui: &mut Ui, items: &mut Vec<String>, new_item: String, editing: &mut bool) -> bool {
+    let mut changed = false;
+    let mut delete_idx = None;
+    for (idx, item) in items.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            let resp = ui.text_edit_singleline(item);
+            changed |= resp.changed();
+            *editing |= resp.has_focus();
+            if ui.button("🗑").clicked() {
+                delete_idx = Some(idx);
+            }
+        });
+    }
+    if let Some(idx) = delete_idx {
+        items.remove(idx);
+        changed = true;
+    }
+    if ui.button("+").clicked() {
+        items.push(new_item);
+        changed = true;
+    }
+    changed
+}
+
+// Lists the result after every step of a markup chain, one per line
+fn markup_breakdown(
+    steps: &[MarkupStep],
+    results: &[f64],
+    unit: &str,
+    show_decimals: bool,
+) -> String {
+    let mut lines = vec![format_float(results[0], Some(unit), show_decimals)];
+    for (step, result) in steps.iter().zip(results.iter().skip(1)) {
+        lines.push(format!(
+            "{}: {}",
+            step.name,
+            format_float(*result, Some(unit), show_decimals)
+        ));
+    }
+    lines.join("\n")
+}
+
+// Edits a fixed value or selects a parameter of the document
+fn factor_edit(
+    ui: &mut Ui,
+    id_source: impl std::hash::Hash,
+    factor: &mut Factor,
+    parameters: &[Parameter],
+) {
+    let selected_text = match factor {
+        Factor::Value(_) => "Value".to_owned(),
+        Factor::Parameter(name) => name.clone(),
+    };
+    egui::ComboBox::from_id_source(id_source)
+        .selected_text(selected_text)
+        .show_ui(ui, |ui| {
+            let is_value = matches!(factor, Factor::Value(_));
+            if ui.selectable_label(is_value, "Value").clicked() && !is_value {
+                *factor = Factor::Value(factor.resolve(parameters));
+            }
+            for parameter in parameters.iter() {
+                let linked = Factor::Parameter(parameter.name.clone());
+                if ui
+                    .selectable_label(*factor == linked, &parameter.name)
+                    .clicked()
+                {
+                    *factor = linked;
+                }
+            }
+        });
+    if let Factor::Value(value) = factor {
+        ui.add(egui::DragValue::new(value));
+    }
+}
+
+// Draws the span of the row's dates within the span of the root row's dates
+fn gantt_bar(
+    ui: &mut Ui,
+    width: f32,
+    enabled: bool,
+    dates: Option<(f64, f64)>,
+    all_dates: Option<(f64, f64)>,
+) -> Response {
+    let height = ui.spacing().interact_size.y;
+    let (rect, resp) = ui.allocate_exact_size(vec2(width, height), Sense::click());
+    let (Some((start, end)), Some((first, last))) = (dates, all_dates) else {
+        return resp;
+    };
+
+    // The end date is the last day of the span
+    let days = (last + 1.0 - first).max(1.0);
+    let x = |date: f64| rect.left() + ((date - first) / days).clamp(0.0, 1.0) as f32 * width;
+    let bar = Rect::from_x_y_ranges(
+        x(start)..=x(end + 1.0),
+        rect.shrink2(vec2(0.0, 4.0)).y_range(),
+    );
+    let color = match enabled {
+        true => ui.visuals().selection.bg_fill,
+        false => ui.visuals().widgets.inactive.bg_fill,
+    };
+    ui.painter().rect_filled(bar, 2.0, color);
+    resp.on_hover_text(format!("{} – {}", format_date(start), format_date(end)))
+}
+
+/// The values of a row which a scenario can override.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct RowValues {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enabled: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    multiplier: Option<f64>,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    col_data: BTreeMap<ColumnID, f64>,
+
+    // None if the scenario removed the quantity of the column
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    quantities: BTreeMap<ColumnID, Option<Quantity>>,
+
+    // None if the scenario removed the category of the column
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    categories: BTreeMap<ColumnID, Option<String>>,
+}
+
+// Sets or removes the entries of the overrides
+fn apply_entries<T: Clone>(
+    entries: &mut BTreeMap<ColumnID, T>,
+    overrides: &BTreeMap<ColumnID, Option<T>>,
+) {
+    for (col_id, value) in overrides.iter() {
+        match value {
+            Some(value) => entries.insert(col_id.clone(), value.clone()),
+            None => entries.remove(col_id),
+        };
+    }
+}
+
+// Restores the base entries of the columns and returns the entries which differed from them
+fn restore_entries<T: Clone + PartialEq>(
+    entries: &mut BTreeMap<ColumnID, T>,
+    base: &BTreeMap<ColumnID, Option<T>>,
+    columns: &[&ColumnID],
+) -> BTreeMap<ColumnID, Option<T>> {
+    let mut changed = BTreeMap::new();
+    for col_id in columns.iter() {
+        let base_value = base.get(*col_id).cloned().flatten();
+        if entries.get(*col_id) == base_value.as_ref() {
+            continue;
+        }
+        changed.insert((*col_id).clone(), entries.get(*col_id).cloned());
+        match base_value {
+            Some(value) => entries.insert((*col_id).clone(), value),
+            None => entries.remove(*col_id),
+        };
+    }
+    changed
+}
+
+/// A named variant of the table, e.g. a premium version of an offer.
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct Scenario {
+    pub(crate) name: String,
+
+    // The values which differ from the base table, by row ID
+    pub(crate) overrides: BTreeMap<RowID, RowValues>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct TreeTable {
+    pub(crate) title_text: String,
+    pub(crate) column_configs: Vec<ColumnConfig>,
+
+    // Holds the values of the active scenario
+    pub(crate) root_row: RowData,
+
+    // Unresolved conflicts of a merge, the table holds our side of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) conflicts: Vec<Conflict>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) scenarios: Vec<Scenario>,
+
+    #[serde(skip)]
+    pub(crate) active_scenario: Option<usize>,
+
+    // The base values of all rows while a scenario is active
+    #[serde(skip)]
+    pub(crate) base_values: BTreeMap<RowID, RowValues>,
+
+    // Named values which factors and multipliers can reference
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) parameters: Vec<Parameter>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) exchange_rates: Vec<ExchangeRate>,
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([';', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+impl TreeTable {
+    fn column_widths(&self) -> HashMap<ColumnID, f32> {
+        (self.column_configs.iter())
+            .map(|cfg| (cfg.id.clone(), cfg.width))
+            .collect()
+    }
+
+    fn apply_column_widths(&mut self, widths: &HashMap<ColumnID, f32>) {
+        for cfg in self.column_configs.iter_mut() {
+            if let Some(width) = widths.get(&cfg.id) {
+                cfg.width = *width;
+            }
+        }
+    }
+
+    /// Parses a document from its JSON representation.
+    pub fn from_json(json: &str) -> serde_json::Result<TreeTable> {
+        let mut table: TreeTable = serde_json::from_str(json)?;
+        table.root_row.assign_missing_ids("row");
+        Ok(table)
+    }
+
+    /// Recalculates all aggregated values which are outdated.
+    pub fn update(&mut self) {
+        if !self.root_row.dirty {
+            return;
+        }
+        let column_configs = calculation_order(&self.column_configs);
+        let total = |root_row: &RowData, cfg: &ColumnConfig| match &cfg.col_type {
+            ColumnType::ShareOfTotal(input_col_id) => root_row.col_data.get(input_col_id).copied(),
+            _ => None,
+        };
+        let old_totals: Vec<Option<f64>> = (column_configs.iter())
+            .map(|cfg| total(&self.root_row, cfg))
+            .collect();
+        (self.root_row).update(&column_configs, &self.parameters, &self.exchange_rates);
+
+        // A changed total changes the shares of all rows, other changes only those of the
+        // changed rows and their children
+        if column_configs.iter().any(|cfg| cfg.col_type.is_share()) {
+            let totals_changed = (column_configs.iter())
+                .zip(old_totals)
+                .any(|(cfg, old_total)| total(&self.root_row, cfg) != old_total);
+            let root_values = self.root_row.col_data.clone();
+            (self.root_row).update_shares(&column_configs, None, &root_values, totals_changed, 1.0);
+        }
+    }
+
+    // Describes the inputs of a sum column whose units can't be converted to the unit of the sum,
+    // and the units of amounts which are left out of the column for the same reason.
+    // Multiplying columns change the unit on purpose, e.g. from h to € with an hourly rate.
+    fn unit_mismatch(&self, col_idx: usize) -> Option<String> {
+        let cfg = self.column_configs.get(col_idx)?;
+        if let Some(units) = self.root_row.unconverted.get(&cfg.id) {
+            let units: Vec<&str> = units.iter().map(String::as_str).collect();
+            return Some(format!(
+                "Amounts in {} are left out, they can't be converted to {}",
+                units.join(", "),
+                cfg.unit
+            ));
+        }
+        if !matches!(
+            cfg.col_type,
+            ColumnType::RowSum(_) | ColumnType::SumIf(_, _)
+        ) {
+            return None;
+        }
+
+        let mismatched: Vec<String> = (cfg.col_type.input_columns().into_iter())
+            .filter_map(|col_id| self.column_configs.iter().find(|c| c.id == *col_id))
+            .filter(|input| {
+                conversion_factor(&input.unit, &cfg.unit, &self.exchange_rates).is_none()
+            })
+            .map(|input| format!("{} ({})", input.caption, input.unit))
+            .collect();
+        (!mismatched.is_empty()).then(|| {
+            format!(
+                "{} can't be converted to {}",
+                mismatched.join(", "),
+                cfg.unit
+            )
+        })
+    }
+
+    // The captions of the columns and the names of the rows which reference the parameter
+    fn parameter_users(&self, name: &str) -> Vec<String> {
+        let mut users: Vec<String> = (self.column_configs.iter())
+            .filter(|cfg| {
+                let uses = |factor: &Factor| *factor == Factor::Parameter(name.to_owned());
+                match &cfg.col_type {
+                    ColumnType::MultiplyByFactor(_, factor) => uses(factor),
+                    ColumnType::Markup(_, steps) => steps.iter().any(|step| uses(&step.value)),
+                    _ => false,
+                }
+            })
+            .map(|cfg| cfg.caption.clone())
+            .collect();
+        self.root_row.collect_parameter_users(name, &mut users);
+        users
+    }
+
+    // Changes the references to a renamed parameter
+    fn rename_parameter(&mut self, old_name: &str, new_name: &str) {
+        for cfg in self.column_configs.iter_mut() {
+            let factors = match &mut cfg.col_type {
+                ColumnType::MultiplyByFactor(_, factor) => vec![factor],
+                ColumnType::Markup(_, steps) => {
+                    steps.iter_mut().map(|step| &mut step.value).collect()
+                }
+                _ => vec![],
+            };
+            for factor in factors {
+                if let Factor::Parameter(name) = factor {
+                    if name == old_name {
+                        *name = new_name.to_owned();
+                    }
+                }
+            }
+        }
+        self.root_row
+            .rename_multiplier_parameter(old_name, new_name);
+    }
+
+    // Updates the rows which are linked to a template, returns whether any row changed
+    fn apply_templates(&mut self, library: &Library) -> bool {
+        let editable: Vec<&ColumnID> = (self.column_configs.iter())
+            .filter(|cfg| cfg.col_type.is_editable())
+            .map(|cfg| &cfg.id)
+            .collect();
+        let changed = self.root_row.apply_templates(library, &editable);
+        if changed {
+            self.root_row.mark_all_dirty();
+        }
+        changed
+    }
+
+    // The linked rows which differ from their template, as ID and name
+    fn outdated_templates(&self, library: &Library) -> Vec<(RowID, String)> {
+        let mut outdated = vec![];
+        (self.root_row).outdated_templates(library, &self.editable_columns(), &mut outdated);
+        outdated
+    }
+
+    fn editable_columns(&self) -> Vec<&ColumnID> {
+        (self.column_configs.iter())
+            .filter(|cfg| cfg.col_type.is_editable())
+            .map(|cfg| &cfg.id)
+            .collect()
+    }
+
+    /// Shows the values of the given scenario, or the base values for None.
+    ///
+    /// The changes made while the previous scenario was active become its overrides.
+    pub(crate) fn set_scenario(&mut self, scenario: Option<usize>) {
+        if scenario == self.active_scenario {
+            return;
+        }
+
+        let editable: Vec<ColumnID> = self.editable_columns().into_iter().cloned().collect();
+        let editable: Vec<&ColumnID> = editable.iter().collect();
+
+        if let Some(idx) = self.active_scenario.take() {
+            let base = std::mem::take(&mut self.base_values);
+            let mut overrides = BTreeMap::new();
+            self.root_row
+                .restore_base_values(&base, &editable, &mut overrides);
+            if let Some(active) = self.scenarios.get_mut(idx) {
+                active.overrides = overrides;
+            }
+        }
+
+        if let Some(next) = scenario.and_then(|idx| self.scenarios.get(idx)) {
+            self.root_row
+                .collect_values(&editable, &mut self.base_values);
+            self.root_row.apply_overrides(&next.overrides);
+            self.active_scenario = scenario;
+        }
+        self.root_row.mark_all_dirty();
+    }
+
+    /// The totals of the root row for the base table and every scenario.
+    pub(crate) fn scenario_totals(&self) -> Vec<(String, BTreeMap<ColumnID, f64>)> {
+        let mut table = self.clone();
+        let names = std::iter::once("Base".to_owned())
+            .chain(self.scenarios.iter().map(|scenario| scenario.name.clone()));
+        let indices = std::iter::once(None).chain((0..self.scenarios.len()).map(Some));
+
+        names
+            .zip(indices)
+            .map(|(name, scenario)| {
+                table.set_scenario(scenario);
+                table.update();
+                (name, table.root_row.col_data.clone())
+            })
+            .collect()
+    }
+
+    /// Pretty-printed JSON with a stable order of all keys, so that version control diffs
+    /// only show what actually changed.
+    pub(crate) fn to_json(&self) -> String {
+        // The document holds the base values and the overrides of the scenarios
+        if self.active_scenario.is_some() {
+            let mut table = self.clone();
+            table.set_scenario(None);
+            return table.to_json();
+        }
+        serde_json::to_string_pretty(&self).unwrap() + "\n"
+    }
+
+    pub(crate) fn load_from_file(file_path: &str) -> Option<TreeTable> {
+        let file_data = std::fs::read_to_string(file_path).ok()?;
+        TreeTable::from_json(file_data.as_str()).ok()
+    }
+
+    fn save_to_file(&self, file_path: &str) {
+        if let Ok(mut file) = File::create(file_path) {
+            let _res = file.write_all(self.to_json().as_bytes());
+        }
+    }
+
+    /// Writes all rows as ';'-separated CSV, leaving out the hidden columns.
+    // Notes, tags and links are appended as the last columns if `with_notes` is set
+    fn to_csv(&self, with_notes: bool) -> String {
+        let columns: Vec<&ColumnConfig> = self
+            .column_configs
+            .iter()
+            .filter(|cfg| !cfg.hidden)
+            .collect();
+
+        let mut header: Vec<String> = ["Level", "Name", "Enabled", "Multiplier"]
+            .map(String::from)
+            .to_vec();
+        for cfg in columns.iter() {
+            header.push(csv_field(&format!("{} ({})", cfg.caption, cfg.unit)));
+        }
+        if with_notes {
+            header.extend(["Notes", "Tags", "Links"].map(String::from));
+        }
+        let mut lines = vec![header.join(";")];
+
+        for row_path in self.root_row.all_paths() {
+            let row = self.root_row.get_row(&row_path).unwrap();
+            let mut fields = vec![
+                row_path.len().to_string(),
+                csv_field(&row.name),
+                row.enabled.to_string(),
+                format!("{}", row.multiplier).replace('.', ","),
+            ];
+            for cfg in columns.iter() {
+                let value = row.col_data.get(&cfg.id);
+                fields.push(match cfg.col_type {
+                    ColumnType::Date(_) => value.map_or("".to_owned(), |date| format_date(*date)),
+                    ColumnType::Gantt(_, _) => "".to_owned(),
+                    ColumnType::Enum(_) => csv_field(row.categories.get(&cfg.id).map_or("", |v| v)),
+                    ColumnType::Boolean if row.children.is_empty() => {
+                        row.categories.contains_key(&cfg.id).to_string()
+                    }
+                    _ => format!("{:.2}", value.unwrap_or(&0.0)).replace('.', ","),
+                });
+            }
+            if with_notes {
+                fields.push(csv_field(&row.notes));
+                fields.push(csv_field(&row.tags.join(", ")));
+                fields.push(csv_field(&row.links.join(" ")));
+            }
+            lines.push(fields.join(";"));
+        }
+
+        // The rates which the amounts of the currency columns were converted with
+        let has_currencies = (columns.iter()).any(|cfg| cfg.col_type == ColumnType::Currency);
+        if has_currencies && !self.exchange_rates.is_empty() {
+            lines.push("".to_owned());
+            lines.push("Currency;Exchange rate".to_owned());
+            for rate in self.exchange_rates.iter() {
+                let rate_text = format!("{}", rate.rate).replace('.', ",");
+                lines.push(format!("{};{}", csv_field(&rate.currency), rate_text));
+            }
+        }
+        // Amounts in these currencies are left out of the sums
+        let missing: BTreeSet<&String> = (columns.iter())
+            .filter(|cfg| cfg.col_type == ColumnType::Currency)
+            .filter_map(|cfg| self.root_row.unconverted.get(&cfg.id))
+            .flatten()
+            .collect();
+        if !missing.is_empty() {
+            lines.push("".to_owned());
+            lines.push("Missing exchange rates".to_owned());
+            lines.extend(missing.into_iter().map(|currency| csv_field(currency)));
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    fn export_csv(&self, file_path: &str, with_notes: bool) {
+        if let Ok(mut file) = File::create(file_path) {
+            let _res = file.write(self.to_csv(with_notes).as_bytes());
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+const MAX_UNDO_STEPS: usize = 50;
+
+/// A row of an undo step. Unchanged subtrees are shared with the step before.
+struct RowSnapshot {
+    // The row without its children
+    row: RowData,
+    children: Vec<Rc<RowSnapshot>>,
+}
+
+impl RowSnapshot {
+    fn new(row: &RowData, previous: Option<&Rc<RowSnapshot>>) -> Rc<RowSnapshot> {
+        // A child is usually at the same position, or next to it after an insertion or removal
+        let previous_child = |idx: usize, id: &str| {
+            let siblings = &previous?.children;
+            [idx, idx.wrapping_sub(1), idx + 1]
+                .into_iter()
+                .filter_map(|idx| siblings.get(idx))
+                .chain(siblings.iter())
+                .find(|child| child.row.id == id)
+        };
+        let children: Vec<Rc<RowSnapshot>> = (row.children.iter().enumerate())
+            .map(|(idx, child)| RowSnapshot::new(child, previous_child(idx, &child.id)))
+            .collect();
+
+        if let Some(previous) = previous {
+            let same_children = children.len() == previous.children.len()
+                && (children.iter().zip(previous.children.iter())).all(|(a, b)| Rc::ptr_eq(a, b));
+            if same_children && previous.row.same_content(row) {
+                return previous.clone();
+            }
+        }
+        Rc::new(RowSnapshot {
+            row: row.without_children(),
+            children,
+        })
+    }
+
+    fn restore(&self) -> RowData {
+        let mut row = self.row.clone();
+        row.children = self.children.iter().map(|child| child.restore()).collect();
+        row
+    }
+}
+
+/// The state of a table at an undo step.
+struct TableSnapshot {
+    // The table without its rows and the base values of its scenarios
+    table: TreeTable,
+    root: Rc<RowSnapshot>,
+    base_values: Rc<BTreeMap<RowID, RowValues>>,
+}
+
+impl TableSnapshot {
+    fn new(tree_table: &TreeTable, previous: Option<&TableSnapshot>) -> TableSnapshot {
+        let base_values = match previous {
+            Some(previous) if *previous.base_values == tree_table.base_values => {
+                previous.base_values.clone()
+            }
+            _ => Rc::new(tree_table.base_values.clone()),
+        };
+        TableSnapshot {
+            table: TreeTable {
+                title_text: tree_table.title_text.clone(),
+                column_configs: tree_table.column_configs.clone(),
+                root_row: RowData::default(),
+                conflicts: tree_table.conflicts.clone(),
+                scenarios: tree_table.scenarios.clone(),
+                active_scenario: tree_table.active_scenario,
+                base_values: BTreeMap::new(),
+                parameters: tree_table.parameters.clone(),
+                exchange_rates: tree_table.exchange_rates.clone(),
+            },
+            root: RowSnapshot::new(&tree_table.root_row, previous.map(|p| &p.root)),
+            base_values,
+        }
+    }
+
+    fn restore(&self) -> TreeTable {
+        let mut tree_table = self.table.clone();
+        tree_table.root_row = self.root.restore();
+        tree_table.base_values = (*self.base_values).clone();
+        tree_table
+    }
+}
+
+/// An open document with its own file, change state and undo history.
+struct Document {
+    tree_table: TreeTable,
+    filename: String,
+
+    // Tells apart the recovery files of unsaved documents, see `autosave::recovery_path`
+    id: String,
+    file_modified: bool,
+
+    // Set by every change, the undo step is recorded once the change is finished
+    changed: bool,
+
+    // The state after the last recorded undo step
+    undo_base: TableSnapshot,
+    undo_stack: Vec<TableSnapshot>,
+    redo_stack: Vec<TableSnapshot>,
+
+    nav: TableNavigation,
+    filter: Option<RowFilter>,
+
+    // The lines of the table are only rebuilt if the tree or its expansion changed
+    table_lines: Vec<TableLine>,
+    table_lines_outdated: bool,
+
+    last_autosave_time: f64,
+
+    // The JSON of the last autosave, so an unchanged document isn't written again
+    autosaved_json: String,
+
+    // A recovery file which is newer than the opened document
+    recovery_offer: Option<PathBuf>,
+
+    // Linked rows which differ from their template, they are only updated when confirmed
+    outdated_templates: Vec<(RowID, String)>,
+
+    // A text of the document is being typed, its undo step is recorded once it loses the focus
+    editing_text: bool,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        let tree_table = TreeTable {
+            title_text: "Tree Tables".to_owned(),
+            column_configs: vec![
+                ColumnConfig {
+                    id: "2387c84a-2c68-405e-a342-d94a1dde6408".to_owned(),
+                    caption: "Materialkosten".to_owned(),
+                    unit: "€".to_owned(),
+                    col_type: ColumnType::Number,
+                    hidden: false,
+                    width: DEFAULT_COLUMN_WIDTH,
+                },
+                ColumnConfig {
+                    id: "94869fe6-c736-4c88-be7f-8084679d78fc".to_owned(),
+                    caption: "Arbeitszeit".to_owned(),
+                    unit: "h".to_owned(),
+                    col_type: ColumnType::Number,
+                    hidden: false,
+                    width: DEFAULT_COLUMN_WIDTH,
+                },
+                ColumnConfig {
+                    id: "5aafbaab-6c03-4e8f-9fc4-cfb66ed2fb16".to_owned(), // Uuid::new_v4().to_string(),
+                    caption: "Verkaufspreis".to_owned(),
+                    unit: "€".to_owned(),
+                    col_type: ColumnType::MultiplyByFactor(
+                        "2387c84a-2c68-405e-a342-d94a1dde6408".to_owned(),
+                        Factor::Value(100.0),
+                    ),
+                    hidden: false,
+                    width: DEFAULT_COLUMN_WIDTH,
+                },
+            ],
+
+            root_row: RowData {
+                name: "∑".to_owned(),
+                children: vec![RowData {
+                    name: "A".to_owned(),
+                    col_data: BTreeMap::from([(
+                        "2387c84a-2c68-405e-a342-d94a1dde6408".to_owned(),
+                        1.0,
+                    )]),
+                    expanded: false,
+                    ..RowData::default()
+                }],
+                expanded: false,
+                ..RowData::default()
+            },
+            conflicts: vec![],
+            scenarios: vec![],
+            active_scenario: None,
+            base_values: BTreeMap::new(),
+            parameters: vec![],
+            exchange_rates: vec![],
+        };
+
+        let mut doc = Document::new(tree_table, "unnamed.tt");
+        doc.file_modified = true;
+        doc
+    }
+}
+
+impl Document {
+    fn new(tree_table: TreeTable, filename: &str) -> Self {
+        Self {
+            undo_base: TableSnapshot::new(&tree_table, None),
+            tree_table,
+            filename: filename.to_owned(),
+            id: Uuid::new_v4().to_string(),
+            file_modified: false,
+            changed: false,
+            undo_stack: vec![],
+            redo_stack: vec![],
+            nav: TableNavigation::default(),
+            filter: None,
+            table_lines: vec![],
+            table_lines_outdated: true,
+            last_autosave_time: 0.0,
+            autosaved_json: "".to_owned(),
+            recovery_offer: None,
+            outdated_templates: vec![],
+            editing_text: false,
+        }
+    }
+
+    // A document which was neither saved nor edited, it is replaced when opening a file
+    fn is_blank(&self) -> bool {
+        !self.changed
+            && self.undo_stack.is_empty()
+            && self.recovery_offer.is_none()
+            && !std::path::Path::new(&self.filename).exists()
+    }
+
+    fn display_name(&self) -> String {
+        let path = std::path::Path::new(&self.filename);
+        let name = path.file_name().map_or(self.filename.clone(), |name| {
+            name.to_string_lossy().to_string()
+        });
+        if self.file_modified {
+            name + "*"
+        } else {
+            name
+        }
+    }
+
+    // Marks the row and its ancestors for recalculation and the document as modified
+    fn mark_modified(&mut self, row_path: &[usize]) {
+        self.tree_table.root_row.mark_dirty(row_path);
+        self.table_lines_outdated = true;
+        self.mark_changed();
+    }
+
+    fn mark_all_modified(&mut self) {
+        self.tree_table.root_row.mark_all_dirty();
+        self.table_lines_outdated = true;
+        self.mark_changed();
+    }
+
+    // Looks for linked rows which differ from the templates of the library, e.g. after a template
+    // changed. They may hold local changes, so they are only updated once the user confirms.
+    fn check_templates(&mut self, library: &Library) {
+        self.outdated_templates = self.tree_table.outdated_templates(library);
+    }
+
+    // Marks a change which doesn't need a recalculation, e.g. of the column order
+    fn mark_changed(&mut self) {
+        self.file_modified = true;
+        self.changed = true;
+    }
+
+    fn record_undo_step(&mut self) {
+        let current = TableSnapshot::new(&self.tree_table, Some(&self.undo_base));
+        let previous = std::mem::replace(&mut self.undo_base, current);
+        self.undo_stack.push(previous);
+        if self.undo_stack.len() > MAX_UNDO_STEPS {
+            self.undo_stack.remove(0);
+        }
+        self.redo_stack.clear();
+        self.changed = false;
+    }
+
+    fn undo(&mut self) {
+        if self.changed {
+            self.record_undo_step();
+        }
+        if let Some(previous) = self.undo_stack.pop() {
+            let current = std::mem::replace(&mut self.undo_base, previous);
+            self.redo_stack.push(current);
+            self.history_changed();
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.changed {
+            return;
+        }
+        if let Some(next) = self.redo_stack.pop() {
+            let current = std::mem::replace(&mut self.undo_base, next);
+            self.undo_stack.push(current);
+            self.history_changed();
+        }
+    }
+
+    // Shows the state of the undo base, but keeps the current expansion of the rows
+    fn history_changed(&mut self) {
+        let mut expanded_ids = HashSet::new();
+        (self.tree_table.root_row).collect_expanded_ids(&mut expanded_ids);
+        let column_widths = self.tree_table.column_widths();
+        self.tree_table = self.undo_base.restore();
+        self.tree_table.root_row.apply_expanded_ids(&expanded_ids);
+        self.tree_table.apply_column_widths(&column_widths);
+
+        self.tree_table.root_row.mark_all_dirty();
+        self.table_lines_outdated = true;
+        self.file_modified = true;
+        self.nav = TableNavigation::default();
+    }
+
+    // Saves the document and drops its recovery file
+    fn save(&mut self) {
+        self.tree_table.save_to_file(self.filename.as_str());
+        self.file_modified = false;
+        autosave::remove_recovery(&self.filename, &self.id);
+    }
+
+    // Writes the modified document to its recovery file, or to the document itself,
+    // once the autosave interval has passed. Returns the seconds until the next autosave.
+    fn autosave(&mut self, now: f64, interval: u32, to_file: bool) -> Option<f64> {
+        // A blank document has nothing worth recovering
+        if interval == 0 || !self.file_modified || self.is_blank() {
+            return None;
+        }
+
+        let remaining = self.last_autosave_time + interval as f64 - now;
+        if remaining > 0.0 {
+            return Some(remaining);
+        }
+        self.last_autosave_time = now;
+
+        // Only documents which were saved before have a real file to autosave to
+        if to_file && std::path::Path::new(&self.filename).exists() {
+            self.save();
+            return None;
+        }
+
+        let json = self.tree_table.to_json();
+        if json != self.autosaved_json {
+            match autosave::write_recovery(&self.filename, &self.id, &json) {
+                Ok(()) => self.autosaved_json = json,
+                Err(err) => log::warn!("Autosave of {} failed: {}", self.filename, err),
+            }
+        }
+        Some(interval as f64)
+    }
+
+    fn store_view_state(
+        &self,
+        expanded_rows: &mut HashMap<String, HashSet<RowID>>,
+        column_widths: &mut HashMap<String, HashMap<ColumnID, f32>>,
+    ) {
+        let mut expanded_ids = HashSet::new();
+        self.tree_table
+            .root_row
+            .collect_expanded_ids(&mut expanded_ids);
+        expanded_rows.insert(self.filename.clone(), expanded_ids);
+        column_widths.insert(self.filename.clone(), self.tree_table.column_widths());
+    }
+
+    fn restore_view_state(
+        &mut self,
+        expanded_rows: &HashMap<String, HashSet<RowID>>,
+        column_widths: &HashMap<String, HashMap<ColumnID, f32>>,
+    ) {
+        if let Some(expanded_ids) = expanded_rows.get(&self.filename) {
+            self.tree_table.root_row.apply_expanded_ids(expanded_ids);
+        }
+        if let Some(widths) = column_widths.get(&self.filename) {
+            self.tree_table.apply_column_widths(widths);
+        }
+        self.table_lines_outdated = true;
+    }
+}
+
+/// We derive Deserialize/Serialize so we can persist app state on shutdown.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)] // if we add new fields, give them default values when deserializing old state
+pub struct TreeTablesApp {
+    // The document of the selected tab
+    #[serde(skip)]
+    doc: Document,
+
+    // The documents of all tabs. The slot of the selected tab is empty while it is in `doc`.
+    #[serde(skip)]
+    documents: Vec<Document>,
+
+    #[serde(skip)]
+    active_document: usize,
+
+    // A tab with unsaved changes which is about to be closed
+    #[serde(skip)]
+    close_tab_requested: Option<usize>,
+
+    #[serde(skip)]
+    compare: Option<CompareView>,
+
+    // The root totals of the base table and every scenario, shown in the scenario summary
+    #[serde(skip)]
+    scenario_totals: Option<Vec<(String, BTreeMap<ColumnID, f64>)>>,
+
+    #[serde(skip)]
+    show_parameters: bool,
+
+    #[serde(skip)]
+    show_exchange_rates: bool,
+
+    // The row templates shared by all documents, read from the library file at startup
+    #[serde(skip)]
+    library: Library,
+
+    // Why the library file couldn't be read. It isn't overwritten then, to not lose templates.
+    #[serde(skip)]
+    library_error: Option<String>,
+
+    #[serde(skip)]
+    show_templates: bool,
+
+    // Whether inserted templates stay linked, so that they follow changes of the template
+    link_templates: bool,
+
+    // The index and new name of the parameter being renamed
+    #[serde(skip)]
+    edit_parameter_name: Option<(usize, String)>,
+
+    // The new name of the active scenario while its name field has the focus
+    #[serde(skip)]
+    edit_scenario_name: Option<String>,
+
+    // Why the parameter which the user tried to remove is still needed
+    #[serde(skip)]
+    parameter_warning: Option<String>,
+
+    #[serde(skip)]
+    edit_title_text: bool,
+
+    #[serde(skip)] // TODO: Implement serialization
+    save_shortcut: Shortcut,
+
+    #[serde(skip)]
+    edit_column_idx: Option<usize>,
+
+    #[serde(skip)]
+    close_requested: bool,
+
+    #[serde(skip)]
+    bulk_edit: BulkEdit,
+
+    // Expanded rows per document file. They are a view preference and not part of the document.
+    expanded_rows: HashMap<String, HashSet<RowID>>,
+
+    // Column widths per document file, so that resizing a column doesn't change the file
+    column_widths: HashMap<String, HashMap<ColumnID, f32>>,
+
+    #[serde(skip)]
+    expand_depth: usize,
+
+    #[serde(skip)]
+    reveal_text: String,
+
+    name_column_width: f32,
+
+    show_decimals: bool,
+
+    // Whether CSV exports include the notes, tags and links of the rows
+    export_notes: bool,
+
+    // Seconds between two autosaves, 0 turns the autosave off
+    autosave_interval: u32,
+
+    // Autosave to the document itself instead of a recovery file
+    autosave_to_file: bool,
+
+    // Most recently used files, the latest first
+    recent_files: Vec<String>,
+
+    reopen_last_document: bool,
+}
+
+impl ColumnConfig {
+    // The units or currencies which values of leaves can be entered in
+    fn entry_units(&self, exchange_rates: &[ExchangeRate]) -> Vec<String> {
+        match self.col_type {
+            ColumnType::Number | ColumnType::Duration => (units::compatible_units(&self.unit)
+                .into_iter())
+            .map(str::to_owned)
+            .collect(),
+            ColumnType::Currency if exchange_rates.iter().any(|r| r.currency == self.unit) => {
+                (exchange_rates.iter())
+                    .map(|rate| rate.currency.clone())
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl Default for ColumnConfig {
+    fn default() -> ColumnConfig {
+        ColumnConfig {
+            id: Uuid::new_v4().to_string(),
+            caption: "".to_owned(),
+            unit: "€".to_owned(),
+            col_type: ColumnType::Number,
+            hidden: false,
+            width: DEFAULT_COLUMN_WIDTH,
+        }
+    }
+}
+
+impl Default for TreeTablesApp {
+    fn default() -> Self {
+        Self {
+            doc: Document::default(),
+            documents: vec![Document::default()],
+            active_document: 0,
+            close_tab_requested: None,
+            compare: None,
+            scenario_totals: None,
+            show_parameters: false,
+            show_exchange_rates: false,
+            library: Library::default(),
+            library_error: None,
+            show_templates: false,
+            link_templates: true,
+            edit_parameter_name: None,
+            edit_scenario_name: None,
+            parameter_warning: None,
+            edit_title_text: false,
+            save_shortcut: Shortcut::new(
+                Some(egui::KeyboardShortcut::new(
+                    egui::Modifiers::COMMAND,
+                    egui::Key::S,
+                )),
+                None,
+            ),
+            edit_column_idx: None,
+            close_requested: false,
+            bulk_edit: BulkEdit::default(),
+            expanded_rows: HashMap::new(),
+            column_widths: HashMap::new(),
+            expand_depth: 1,
+            reveal_text: "".to_owned(),
+            name_column_width: DEFAULT_NAME_COLUMN_WIDTH,
+            show_decimals: false,
+            export_notes: false,
+            autosave_interval: 60,
+            autosave_to_file: false,
+            recent_files: vec![],
+            reopen_last_document: true,
+        }
+    }
+}
+
+impl TreeTablesApp {
+    /// Called once before the first frame.
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        // This is also where you can customize the look and feel of egui using
+        // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
+
+        // Load previous app state (if any).
+        // Note that you must enable the `persistence` feature for this to work.
+        let mut app: Self = match cc.storage {
+            Some(storage) => eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default(),
+            None => Default::default(),
+        };
+        match Library::load() {
+            Ok(library) => app.library = library,
+            Err(err) => {
+                log::warn!("Could not read the template library: {err}");
+                app.library_error = Some(err.to_string());
+            }
+        }
+        if app.reopen_last_document {
+            if let Some(filename) = app.recent_files.first().cloned() {
+                app.open_document(&filename);
+            }
+        }
+        // Unsaved documents of the last session which weren't saved or discarded, e.g. after a
+        // crash, are offered for recovery in their own tabs
+        for (recovery, filename, id) in autosave::unsaved_recoveries() {
+            let doc = Document {
+                filename,
+                id,
+                recovery_offer: Some(recovery),
+                ..Document::default()
+            };
+            app.add_document(doc);
+        }
+        app
+    }
+
+    // Opens a document file in a new tab and moves it to the top of the recent files
+    fn open_document(&mut self, filename: &str) {
+        if let Some(idx) = self.document_index(filename) {
+            self.select_document(idx);
+            self.add_recent_file();
+            return;
+        }
+
+        self.recent_files.retain(|recent| recent != filename);
+        let Some(tree_table) = TreeTable::load_from_file(filename) else {
+            log::warn!("Could not open {}", filename);
+            return;
+        };
+
+        let mut doc = Document::new(tree_table, filename);
+        doc.recovery_offer = autosave::newer_recovery(filename, &doc.id);
+        doc.restore_view_state(&self.expanded_rows, &self.column_widths);
+        doc.check_templates(&self.library);
+        self.add_document(doc);
+        self.add_recent_file();
+    }
+
+    // Shows the document in a new tab, or instead of a blank document
+    fn add_document(&mut self, doc: Document) {
+        if self.doc.is_blank() {
+            self.doc = doc;
+        } else {
+            self.documents.push(doc);
+            self.select_document(self.documents.len() - 1);
+        }
+    }
+
+    fn add_recent_file(&mut self) {
+        self.recent_files
+            .retain(|recent| *recent != self.doc.filename);
+        self.recent_files.insert(0, self.doc.filename.clone());
+        self.recent_files.truncate(MAX_RECENT_FILES);
+    }
+
+    fn document_index(&self, filename: &str) -> Option<usize> {
+        if self.doc.filename == filename {
+            return Some(self.active_document);
+        }
+        (self.documents.iter().enumerate())
+            .position(|(idx, doc)| idx != self.active_document && doc.filename == filename)
+    }
+
+    fn select_document(&mut self, idx: usize) {
+        if idx == self.active_document {
+            return;
+        }
+        std::mem::swap(&mut self.doc, &mut self.documents[self.active_document]);
+        self.active_document = idx;
+        std::mem::swap(&mut self.doc, &mut self.documents[idx]);
+        self.edit_column_idx = None;
+        self.edit_title_text = false;
+        self.scenario_totals = None;
+        self.edit_parameter_name = None;
+        self.edit_scenario_name = None;
+        self.parameter_warning = None;
+    }
+
+    fn close_document(&mut self, idx: usize) {
+        self.select_document(idx);
+        (self.doc).store_view_state(&mut self.expanded_rows, &mut self.column_widths);
+
+        self.documents.remove(idx);
+        if self.documents.is_empty() {
+            self.documents.push(Document::default());
+        }
+        self.active_document = idx.min(self.documents.len() - 1);
+        self.doc = std::mem::take(&mut self.documents[self.active_document]);
+    }
+
+    // All open documents in the order of their tabs
+    fn all_documents(&self) -> impl Iterator<Item = &Document> {
+        let active = self.active_document;
+        (self.documents.iter().enumerate())
+            .map(move |(idx, doc)| if idx == active { &self.doc } else { doc })
+    }
+
+    fn all_documents_mut(&mut self) -> impl Iterator<Item = &mut Document> {
+        let active = self.active_document;
+        let others = (self.documents.iter_mut().enumerate())
+            .filter(move |(idx, _)| *idx != active)
+            .map(|(_, doc)| doc);
+        std::iter::once(&mut self.doc).chain(others)
+    }
+
+    fn autosave(&mut self, ctx: &egui::Context) {
+        let now = ctx.input(|i| i.time);
+        let (interval, to_file) = (self.autosave_interval, self.autosave_to_file);
+        for doc in self.all_documents_mut() {
+            if let Some(remaining) = doc.autosave(now, interval, to_file) {
+                // Wake up for the next autosave even if there is no input
+                ctx.request_repaint_after(Duration::from_secs_f64(remaining));
+            }
+        }
+    }
+
+    fn recovery_window(&mut self, ctx: &egui::Context) {
+        let Some(recovery) = self.doc.recovery_offer.clone() else {
+            return;
+        };
+
+        egui::Window::new("Recover unsaved changes")
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "There are autosaved changes of {} which are newer than the saved file.",
+                    self.doc.filename
+                ));
+                ui.label("Do you want to restore them?");
+                ui.horizontal(|ui| {
+                    if ui.button("Restore").clicked() {
+                        match TreeTable::load_from_file(&recovery.display().to_string()) {
+                            Some(tree_table) => {
+                                let mut doc = Document::new(tree_table, &self.doc.filename);
+                                doc.id = self.doc.id.clone();
+                                doc.restore_view_state(&self.expanded_rows, &self.column_widths);
+                                doc.check_templates(&self.library);
+                                doc.file_modified = true;
+                                self.doc = doc;
+                            }
+                            None => log::warn!("Recovery file {} is corrupted", recovery.display()),
+                        }
+                        self.doc.recovery_offer = None;
+                    }
+                    if ui.button("Discard").clicked() {
+                        autosave::remove_recovery(&self.doc.filename, &self.doc.id);
+                        self.doc.recovery_offer = None;
+                    }
+                });
+            });
+    }
+
+    // Asks whether to update the linked rows which differ from their template
+    fn outdated_templates_window(&mut self, ctx: &egui::Context) {
+        if self.doc.outdated_templates.is_empty() {
+            return;
+        }
+
+        let mut update = None;
+        egui::Window::new("Changed templates")
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label("These linked rows differ from their template in the library:");
+                for (_, name) in self.doc.outdated_templates.iter() {
+                    ui.label(format!("🧩 {name}"));
+                }
+                ui.label("Updating them replaces their local changes.");
+                ui.horizontal(|ui| {
+                    if ui.button("Update rows").clicked() {
+                        update = Some(true);
+                    }
+                    if ui.button("Keep and unlink").clicked() {
+                        update = Some(false);
+                    }
+                    if ui.button("Not now").clicked() {
+                        self.doc.outdated_templates.clear();
+                    }
+                });
+            });
+
+        match update {
+            Some(true) => {
+                self.doc.tree_table.apply_templates(&self.library);
+                self.doc.mark_all_modified();
+            }
+            Some(false) => {
+                for (id, _) in self.doc.outdated_templates.iter() {
+                    let root = &mut self.doc.tree_table.root_row;
+                    if let Some(row_path) = root.find_path(&|row| row.id == *id) {
+                        root.get_row_mut(&row_path).unwrap().unlink_templates();
+                    }
+                }
+                self.doc.mark_changed();
+            }
+            None => return,
+        }
+        self.doc.outdated_templates.clear();
+    }
+
+    // Asks whether to save a modified tab before closing it
+    fn close_tab_window(&mut self, ctx: &egui::Context) {
+        let Some(idx) = self.close_tab_requested else {
+            return;
+        };
+        self.select_document(idx);
+
+        egui::Window::new("Close tab")
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} has unsaved changes. Do you want to save them?",
+                    self.doc.filename
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        self.doc.save();
+                        self.close_document(idx);
+                        self.close_tab_requested = None;
+                    }
+                    if ui.button("Discard").clicked() {
+                        autosave::remove_recovery(&self.doc.filename, &self.doc.id);
+                        self.close_document(idx);
+                        self.close_tab_requested = None;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.close_tab_requested = None;
+                    }
+                });
+            });
+    }
+
+    // Compares the given table as the old state with the document of the selected tab
+    fn compare_with(&mut self, old_name: String, old: &TreeTable) {
+        self.compare = Some(CompareView {
+            old_name,
+            new_name: self.doc.filename.clone(),
+            diff: TableDiff::new(old, &self.doc.tree_table),
+            show_unchanged: false,
+        });
+    }
+
+    fn compare_menu(&mut self, ui: &mut Ui) {
+        if !cfg!(target_arch = "wasm32") && ui.button("Compare with file…").clicked() {
+            ui.close_menu();
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("Tree-Tables", &VALID_FILE_EXTENSIONS)
+                .pick_file()
+            {
+                let filename = path.display().to_string();
+                match TreeTable::load_from_file(&filename) {
+                    Some(old) => self.compare_with(filename, &old),
+                    None => log::warn!("Could not open {}", filename),
+                }
+            }
+        }
+
+        let mut picked = None;
+        for (idx, doc) in self.all_documents().enumerate() {
+            if idx != self.active_document
+                && ui
+                    .button(format!("Compare with {}", doc.filename))
+                    .clicked()
+            {
+                ui.close_menu();
+                picked = Some(idx);
+            }
+        }
+        // Only the picked table is copied, the menu is drawn every frame
+        if let Some(doc) = picked.and_then(|idx| self.all_documents().nth(idx)) {
+            let (filename, old) = (doc.filename.clone(), doc.tree_table.clone());
+            self.compare_with(filename, &old);
+        }
+
+        if !cfg!(target_arch = "wasm32") {
+            ui.separator();
+            if ui.button("Merge with…").clicked() {
+                ui.close_menu();
+                self.merge_files_into_document();
+            }
+        }
+    }
+
+    // Merges the changes of a file since a common base file into the current document
+    fn merge_files_into_document(&mut self) {
+        let pick = |title: &str| {
+            rfd::FileDialog::new()
+                .set_title(title)
+                .add_filter("Tree-Tables", &VALID_FILE_EXTENSIONS)
+                .pick_file()
+                .map(|path| path.display().to_string())
+        };
+        let Some(base_path) = pick("Common base version") else {
+            return;
+        };
+        let Some(theirs_path) = pick("Version to merge") else {
+            return;
+        };
+
+        let (Some(base), Some(theirs)) = (
+            TreeTable::load_from_file(&base_path),
+            TreeTable::load_from_file(&theirs_path),
+        ) else {
+            log::warn!("Could not open {} or {}", base_path, theirs_path);
+            return;
+        };
+
+        let mut expanded_ids = HashSet::new();
+        self.doc
+            .tree_table
+            .root_row
+            .collect_expanded_ids(&mut expanded_ids);
+        // Merges the base values, the scenario overrides are kept
+        self.doc.tree_table.set_scenario(None);
+        self.doc.tree_table = merge(&base, &self.doc.tree_table, &theirs);
+        self.doc
+            .tree_table
+            .root_row
+            .apply_expanded_ids(&expanded_ids);
+        self.doc.nav = TableNavigation::default();
+        self.doc.mark_all_modified();
+    }
+
+    // Lists the unresolved conflicts of a merge, each one is resolved by picking a side
+    fn conflicts_window(&mut self, ctx: &egui::Context) {
+        if self.doc.tree_table.conflicts.is_empty() {
+            return;
+        }
+
+        let mut resolved = None;
+        egui::Window::new("Merge conflicts")
+            .default_size([600.0, 300.0])
+            .show(ctx, |ui| {
+                let conflicts = &self.doc.tree_table.conflicts;
+                ui.label(format!("{} unresolved conflicts", conflicts.len()));
+                ui.horizontal(|ui| {
+                    if ui.button("Use all ours").clicked() {
+                        resolved = Some((None, false));
+                    }
+                    if ui.button("Use all theirs").clicked() {
+                        resolved = Some((None, true));
+                    }
+                });
+                ui.separator();
+
+                egui::ScrollArea::both().show(ui, |ui| {
+                    egui::Grid::new("conflicts_grid")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for label in ["Location", "Field", "Base", "Ours", "Theirs"] {
+                                ui.strong(label);
+                            }
+                            ui.end_row();
+
+                            for (idx, conflict) in conflicts.iter().enumerate() {
+                                ui.label(&conflict.location);
+                                ui.label(format!("{:?}", conflict.field));
+                                ui.label(conflict.base.to_string());
+                                if ui.button(conflict.ours.to_string()).clicked() {
+                                    resolved = Some((Some(idx), false));
+                                }
+                                if ui.button(conflict.theirs.to_string()).clicked() {
+                                    resolved = Some((Some(idx), true));
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+            });
+
+        match resolved {
+            Some((Some(idx), use_theirs)) => {
+                self.doc.tree_table.resolve_conflict(idx, use_theirs);
+            }
+            Some((None, use_theirs)) => {
+                while !self.doc.tree_table.conflicts.is_empty() {
+                    self.doc.tree_table.resolve_conflict(0, use_theirs);
+                }
+            }
+            None => return,
+        }
+        self.doc.nav = TableNavigation::default();
+        self.doc.mark_all_modified();
+    }
+
+    fn compare_window(&mut self, ctx: &egui::Context) {
+        let Some(view) = self.compare.as_mut() else {
+            return;
+        };
+
+        let mut open = true;
+        egui::Window::new("Compare")
+            .open(&mut open)
+            .default_size([800.0, 400.0])
+            .show(ctx, |ui| {
+                ui.label(format!("{} → {}", view.old_name, view.new_name));
+                ui.checkbox(&mut view.show_unchanged, "Show unchanged rows");
+                if view.diff.is_empty() {
+                    ui.label("The tables are equal.");
+                }
+
+                for col in view.diff.columns.iter() {
+                    if col.status != Status::Unchanged {
+                        let mut text = format!("Column {} {:?}", col.caption, col.status);
+                        if let Some(old_caption) = &col.old_caption {
+                            text += &format!(" (was {old_caption})");
+                        }
+                        ui.colored_label(diff_status_color(ui, col.status), text);
+                    }
+                }
+                ui.separator();
+
+                let view = &*view;
+                egui::ScrollArea::both().show(ui, |ui| {
+                    egui::Grid::new("compare_grid")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.strong("Row");
+                            ui.strong("");
+                            for col in view.diff.columns.iter() {
+                                ui.strong(format!("{} old", col.caption));
+                                ui.strong(format!("{} new", col.caption));
+                                ui.strong("Δ");
+                            }
+                            ui.end_row();
+
+                            show_diff_row(ui, view, &view.diff.root, 0);
+                        });
+                });
+            });
+
+        if !open {
+            self.compare = None;
+        }
+    }
+
+    fn scenario_bar(&mut self, ui: &mut Ui) {
+        let table = &mut self.doc.tree_table;
+        let mut selected = table.active_scenario;
+        let mut changed = false;
+        let mut removed = false;
+
+        ui.horizontal(|ui| {
+            ui.label("Scenario:");
+            let scenario_name =
+                |idx: Option<usize>| idx.map_or("Base", |idx| &table.scenarios[idx].name);
+            egui::ComboBox::from_id_source("scenario")
+                .selected_text(scenario_name(selected).to_owned())
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut selected, None, "Base");
+                    for (idx, scenario) in table.scenarios.iter().enumerate() {
+                        ui.selectable_value(&mut selected, Some(idx), &scenario.name);
+                    }
+                });
+
+            if ui.button("+").on_hover_text("Add scenario").clicked() {
+                table.scenarios.push(Scenario {
+                    name: format!("Scenario {}", table.scenarios.len() + 1),
+                    overrides: BTreeMap::new(),
+                });
+                selected = Some(table.scenarios.len() - 1);
+                changed = true;
+            }
+
+            if let Some(idx) = table.active_scenario {
+                let scenario = &mut table.scenarios[idx];
+                let name = (self.edit_scenario_name).get_or_insert_with(|| scenario.name.clone());
+                let response = ui.text_edit_singleline(name);
+                // The name is only taken once it's entered, not on every keystroke
+                if response.lost_focus() && !name.is_empty() && *name != scenario.name {
+                    scenario.name = name.clone();
+                    changed = true;
+                }
+                if !response.has_focus() {
+                    self.edit_scenario_name = None;
+                }
+                if ui.button("🗑").on_hover_text("Remove scenario").clicked() {
+                    // Leaving the scenario first restores the base values
+                    table.set_scenario(None);
+                    table.scenarios.remove(idx);
+                    selected = None;
+                    removed = true;
+                }
+            }
+
+            if !table.scenarios.is_empty() && ui.button("Compare scenarios").clicked() {
+                self.scenario_totals = Some(table.scenario_totals());
+            }
+        });
+
+        // The values of the rows change with the scenario
+        if selected != self.doc.tree_table.active_scenario || removed {
+            self.doc.tree_table.set_scenario(selected);
+            self.doc.mark_all_modified();
+        } else if changed {
+            self.doc.mark_changed();
+        }
+    }
+
+    // Filters the rows by the value of a Boolean or Enum column, or by a tag
+    fn filter_bar(&mut self, ui: &mut Ui) {
+        let columns: Vec<&ColumnConfig> = (self.doc.tree_table.column_configs.iter())
+            .filter(|cfg| cfg.col_type.is_categorical())
+            .collect();
+        let tags = self.doc.tree_table.root_row.all_tags();
+        // A filter by a removed or changed column is dropped
+        let mut filter = (self.doc.filter.clone()).filter(|f| match f {
+            RowFilter::Category { col_id, .. } => columns.iter().any(|cfg| cfg.id == *col_id),
+            RowFilter::Tag(_) => true,
+        });
+
+        if !columns.is_empty() || !tags.is_empty() {
+            ui.horizontal(|ui| {
+                ui.label("Filter:");
+                let selected_text = match &filter {
+                    None => "All rows",
+                    Some(RowFilter::Tag(_)) => "Tag",
+                    Some(RowFilter::Category { col_id, .. }) => (columns.iter())
+                        .find(|cfg| cfg.id == *col_id)
+                        .map_or("", |cfg| &cfg.caption),
+                };
+                egui::ComboBox::from_id_source("filter_column")
+                    .selected_text(selected_text.to_owned())
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut filter, None, "All rows");
+                        for cfg in columns.iter() {
+                            let is_selected = matches!(&filter,
+                                Some(RowFilter::Category { col_id, .. }) if *col_id == cfg.id);
+                            if ui.selectable_label(is_selected, &cfg.caption).clicked()
+                                && !is_selected
+                            {
+                                let value = match cfg.col_type {
+                                    ColumnType::Boolean => Some("true".to_owned()),
+                                    _ => None,
+                                };
+                                filter = Some(RowFilter::Category {
+                                    col_id: cfg.id.clone(),
+                                    value,
+                                });
+                            }
+                        }
+                        let is_selected = matches!(filter, Some(RowFilter::Tag(_)));
+                        if let Some(first_tag) = tags.first() {
+                            if ui.selectable_label(is_selected, "Tag").clicked() && !is_selected {
+                                filter = Some(RowFilter::Tag(first_tag.clone()));
+                            }
+                        }
+                    });
+
+                match filter.as_mut() {
+                    Some(RowFilter::Category { col_id, value }) => {
+                        let Some(cfg) = columns.iter().find(|cfg| cfg.id == *col_id) else {
+                            return;
+                        };
+                        let values: Vec<(Option<String>, String)> = match &cfg.col_type {
+                            ColumnType::Enum(values) => std::iter::once((None, "–".to_owned()))
+                                .chain(values.iter().map(|v| (Some(v.clone()), v.clone())))
+                                .collect(),
+                            _ => vec![
+                                (Some("true".to_owned()), "Checked".to_owned()),
+                                (None, "Unchecked".to_owned()),
+                            ],
+                        };
+                        let selected_text = (values.iter())
+                            .find(|(v, _)| v == value)
+                            .map_or("", |(_, text)| text)
+                            .to_owned();
+                        egui::ComboBox::from_id_source("filter_value")
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
+                                for (v, text) in values {
+                                    ui.selectable_value(value, v, text);
+                                }
+                            });
+                    }
+                    Some(RowFilter::Tag(tag)) => {
+                        egui::ComboBox::from_id_source("filter_tag")
+                            .selected_text(tag.clone())
+                            .show_ui(ui, |ui| {
+                                for t in tags.iter() {
+                                    ui.selectable_value(tag, t.clone(), t);
+                                }
+                            });
+                    }
+                    None => (),
+                }
+            });
+        }
+
+        if filter != self.doc.filter {
+            self.doc.filter = filter;
+            self.doc.table_lines_outdated = true;
+        }
+    }
+
+    fn scenario_summary_window(&mut self, ctx: &egui::Context) {
+        let Some(totals) = self.scenario_totals.as_ref() else {
+            return;
+        };
+
+        let mut open = true;
+        let mut refresh = false;
+        egui::Window::new("Scenarios")
+            .open(&mut open)
+            .default_size([600.0, 200.0])
+            .show(ctx, |ui| {
+                if ui.button("Refresh").clicked() {
+                    refresh = true;
+                }
+                ui.separator();
+
+                let columns: Vec<&ColumnConfig> = (self.doc.tree_table.column_configs.iter())
+                    .filter(|cfg| !cfg.hidden)
+                    .collect();
+                let base = &totals[0].1;
+                let value = |totals: &BTreeMap<ColumnID, f64>, col_id: &ColumnID| {
+                    *totals.get(col_id).unwrap_or(&0.0)
+                };
+
+                egui::ScrollArea::both().show(ui, |ui| {
+                    egui::Grid::new("scenarios_grid")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            ui.strong("Scenario");
+                            for cfg in columns.iter() {
+                                ui.strong(&cfg.caption);
+                                ui.strong("Δ Base");
+                            }
+                            ui.end_row();
+
+                            for (name, scenario_totals) in totals.iter() {
+                                ui.label(name);
+                                for cfg in columns.iter() {
+                                    let total = value(scenario_totals, &cfg.id);
+                                    let delta = total - value(base, &cfg.id);
+                                    ui.label(format_float(
+                                        total,
+                                        Some(&cfg.unit),
+                                        self.show_decimals,
+                                    ));
+                                    ui.label(format_float(delta, None, self.show_decimals));
+                                }
+                                ui.end_row();
+                            }
+                        });
+                });
+            });
+
+        if !open || self.doc.tree_table.scenarios.is_empty() {
+            self.scenario_totals = None;
+        } else if refresh {
+            self.scenario_totals = Some(self.doc.tree_table.scenario_totals());
+        }
+    }
+
+    // Lists the parameters of the document, changing one recalculates the whole table
+    // Edits the notes, tags and links of the row chosen in the context menu of its name
+    fn row_details_window(&mut self, ctx: &egui::Context) {
+        let Some(row_path) = self.doc.nav.details_row.clone() else {
+            return;
+        };
+        let Some(row) = self.doc.tree_table.root_row.get_row_mut(&row_path) else {
+            self.doc.nav.details_row = None;
+            self.doc.editing_text = false;
+            return;
+        };
+
+        let mut open = true;
+        let mut changed = false;
+        let mut editing = false;
+        egui::Window::new(format!("Notes of {}", row.name))
+            .id(Id::new("row_details"))
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label("Notes (Markdown):");
+                let resp = ui.add(egui::TextEdit::multiline(&mut row.notes).desired_rows(6));
+                changed |= resp.changed();
+                editing |= resp.has_focus();
+
+                ui.label("Tags:");
+                changed |= edit_list(ui, &mut row.tags, "tag".to_owned(), &mut editing);
+
+                ui.label("Links to files or URLs:");
+                changed |= edit_list(ui, &mut row.links, "https://".to_owned(), &mut editing);
+            });
+
+        if !open {
+            self.doc.nav.details_row = None;
+            editing = false;
+        }
+        self.doc.editing_text = editing;
+        if changed {
+            self.doc.mark_changed();
+            self.doc.table_lines_outdated = true;
+        }
+    }
+
+    fn parameters_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_parameters;
+        let mut changed = false;
+        let mut renamed = None;
+        let mut removed = None;
+
+        egui::Window::new("Parameters")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                let parameters = &mut self.doc.tree_table.parameters;
+                let names: Vec<String> = parameters.iter().map(|p| p.name.clone()).collect();
+
+                egui::Grid::new("parameters_grid").show(ui, |ui| {
+                    for (idx, parameter) in parameters.iter_mut().enumerate() {
+                        match &mut self.edit_parameter_name {
+                            Some((edit_idx, name)) if *edit_idx == idx => {
+                                if ui.text_edit_singleline(name).lost_focus() {
+                                    // Names are unique, they are the references to the parameter
+                                    if !name.is_empty() && !names.contains(name) {
+                                        renamed = Some((parameter.name.clone(), name.clone()));
+                                        parameter.name = name.clone();
+                                    }
+                                    self.edit_parameter_name = None;
+                                }
+                            }
+                            _ => {
+                                if ui.label(&parameter.name).double_clicked() {
+                                    self.edit_parameter_name = Some((idx, parameter.name.clone()));
+                                }
+                            }
+                        }
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut parameter.value).speed(0.1))
+                            .changed();
+                        if ui.button("🗑").clicked() {
+                            removed = Some(idx);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                if ui.button("+").on_hover_text("Add parameter").clicked() {
+                    let name = (1..)
+                        .map(|n| format!("parameter_{n}"))
+                        .find(|name| !names.contains(name))
+                        .unwrap();
+                    parameters.push(Parameter { name, value: 1.0 });
+                    changed = true;
+                }
+                ui.label("Double-click a name to rename the parameter.");
+                if let Some(warning) = &self.parameter_warning {
+                    ui.colored_label(ui.visuals().warn_fg_color, warning);
+                }
+            });
+
+        self.show_parameters = open;
+        if !open {
+            self.parameter_warning = None;
+        }
+        if let Some((old_name, new_name)) = renamed {
+            self.doc.tree_table.rename_parameter(&old_name, &new_name);
+            changed = true;
+        }
+        if let Some(idx) = removed {
+            // Removing a parameter which is still referenced would set its users to 0
+            let table = &mut self.doc.tree_table;
+            let users = table.parameter_users(&table.parameters[idx].name);
+            if users.is_empty() {
+                table.parameters.remove(idx);
+                self.edit_parameter_name = None;
+                self.parameter_warning = None;
+                changed = true;
+            } else {
+                self.parameter_warning = Some(format!(
+                    "{} can't be removed, it's used by {}.",
+                    table.parameters[idx].name,
+                    users.join(", ")
+                ));
+            }
+        }
+        if changed {
+            self.doc.mark_all_modified();
+        }
+    }
+
+    // Lists the exchange rates of the document, which can also be imported from a CSV file
+    fn exchange_rates_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_exchange_rates;
+        let mut changed = false;
+        let mut removed = None;
+        let mut imported = vec![];
+
+        egui::Window::new("Exchange rates")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label("The value of one unit of each currency in a common reference currency.");
+                let exchange_rates = &mut self.doc.tree_table.exchange_rates;
+                egui::Grid::new("exchange_rates_grid").show(ui, |ui| {
+                    for (idx, rate) in exchange_rates.iter_mut().enumerate() {
+                        changed |= ui
+                            .add(egui::TextEdit::singleline(&mut rate.currency).desired_width(60.0))
+                            .changed();
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut rate.rate).speed(0.01))
+                            .changed();
+                        if ui.button("🗑").clicked() {
+                            removed = Some(idx);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button("+").on_hover_text("Add exchange rate").clicked() {
+                        exchange_rates.push(ExchangeRate {
+                            currency: "".to_owned(),
+                            rate: 1.0,
+                        });
+                        changed = true;
+                    }
+                    if ui.button("Import CSV…").clicked() {
+                        let path = rfd::FileDialog::new()
+                            .add_filter("CSV", &["csv", "txt"])
+                            .pick_file();
+                        if let Some(text) = path.and_then(|p| std::fs::read_to_string(p).ok()) {
+                            imported = parse_exchange_rates(&text);
+                        }
+                    }
+                });
+            });
+
+        self.show_exchange_rates = open;
+        let exchange_rates = &mut self.doc.tree_table.exchange_rates;
+        if let Some(idx) = removed {
+            exchange_rates.remove(idx);
+            changed = true;
+        }
+        // Imported rates replace the rates of the same currency
+        for rate in imported {
+            changed = true;
+            match exchange_rates
+                .iter_mut()
+                .find(|r| r.currency == rate.currency)
+            {
+                Some(existing) => existing.rate = rate.rate,
+                None => exchange_rates.push(rate),
+            }
+        }
+        if changed {
+            self.doc.mark_all_modified();
+        }
+    }
+
+    fn tab_bar(&mut self, ui: &mut Ui) {
+        let tabs: Vec<String> = self.all_documents().map(Document::display_name).collect();
+
+        ui.horizontal(|ui| {
+            for (idx, name) in tabs.into_iter().enumerate() {
+                if ui
+                    .selectable_label(idx == self.active_document, name)
+                    .clicked()
+                {
+                    self.select_document(idx);
+                }
+                if ui.small_button("x").clicked() {
+                    self.select_document(idx);
+                    if self.doc.file_modified {
+                        self.close_tab_requested = Some(idx);
+                    } else {
+                        self.close_document(idx);
+                    }
+                }
+                ui.separator();
+            }
+        });
+    }
+
+    // Reveals the first row whose name contains the given text and moves the cursor to it
+    fn reveal_row(&mut self, text: &str) {
+        let text = text.to_lowercase();
+        let found = self
+            .doc
+            .tree_table
+            .root_row
+            .find_path(&|row| row.name.to_lowercase().contains(&text));
+
+        if let Some(row_path) = found {
+            self.doc.tree_table.root_row.reveal(&row_path);
+            self.doc.table_lines_outdated = true;
+            self.doc.nav.set_cursor(&row_path, 0);
+            self.doc.nav.scroll_to_cursor = true;
+        }
+    }
+
+    /// Moves the keyboard cursor by the given number of visible rows and columns.
+    fn move_cursor(&mut self, row_delta: i32, col_delta: i32) {
+        let Some(cursor) = self.doc.nav.cursor.as_mut() else {
+            return;
+        };
+
+        let visible_paths = (self.doc.tree_table.root_row).visible_paths(self.doc.filter.as_ref());
+        let row_pos = visible_paths
+            .iter()
+            .position(|p| *p == cursor.row_path)
+            .unwrap_or(0) as i32;
+        let row_pos = (row_pos + row_delta).clamp(0, visible_paths.len() as i32 - 1);
+
+        // The name column and all columns which are not hidden
+        let visible_cols: Vec<usize> = std::iter::once(0)
+            .chain(
+                (self.doc.tree_table.column_configs.iter().enumerate())
+                    .filter(|(_, cfg)| !cfg.hidden)
+                    .map(|(i, _)| i + 1),
+            )
+            .collect();
+        let col_pos = visible_cols
+            .iter()
+            .position(|c| *c == cursor.col_idx)
+            .unwrap_or(0) as i32;
+        let col_pos = (col_pos + col_delta).clamp(0, visible_cols.len() as i32 - 1);
+
+        cursor.row_path = visible_paths[row_pos as usize].clone();
+        cursor.col_idx = visible_cols[col_pos as usize];
+        self.doc.nav.scroll_to_cursor = true;
+    }
+
+    // The text to start editing the given cell with, None if the cell is read-only
+    fn cell_edit_text(&self, cursor: &CellCursor) -> Option<String> {
+        let row = self.doc.tree_table.root_row.get_row(&cursor.row_path)?;
+        if cursor.col_idx == 0 {
+            return Some(row.name.clone());
+        }
+
+        let col_cfg = self.doc.tree_table.column_configs.get(cursor.col_idx - 1)?;
+        if !row.children.is_empty() || !col_cfg.col_type.is_editable() || !row.enabled {
+            return None;
+        }
+        if let Some(quantity) = row.quantities.get(&col_cfg.id) {
+            return Some(format_float(quantity.amount, Some(&quantity.unit), true));
+        }
+        if let ColumnType::Date(_) = col_cfg.col_type {
+            return Some(
+                row.col_data
+                    .get(&col_cfg.id)
+                    .map_or("".to_owned(), |d| format_date(*d)),
+            );
+        }
+        match col_cfg.col_type {
+            ColumnType::Enum(_) => return Some(row.categories.get(&col_cfg.id)?.clone()),
+            ColumnType::Boolean => return None,
+            _ => (),
+        }
+        let value = *row.col_data.get(&col_cfg.id).unwrap_or(&0.0);
+        Some(format_float(value, None, true))
+    }
+
+    fn commit_cell_edit(&mut self, cursor: &CellCursor) {
+        let Some(text) = self.doc.nav.edit_buffer.take() else {
+            return;
+        };
+        let Some(row) = self.doc.tree_table.root_row.get_row_mut(&cursor.row_path) else {
+            return;
+        };
+
+        if cursor.col_idx == 0 {
+            if !text.is_empty() && text != row.name {
+                row.name = text;
+                row.edit_name = false;
+                self.doc.mark_changed();
+            }
+        } else if let Some(col_cfg) = self.doc.tree_table.column_configs.get(cursor.col_idx - 1) {
+            // A number without a unit keeps the unit of the cell
+            let col_id = &col_cfg.id;
+            match parse_quantity(&text) {
+                // Only the allowed values of an Enum column, or an empty text to clear it
+                _ if matches!(&col_cfg.col_type, ColumnType::Enum(values) if values.contains(&text)) =>
+                {
+                    row.categories.insert(col_id.clone(), text);
+                }
+                _ if col_cfg.col_type.is_categorical() => match text.trim().is_empty() {
+                    true => {
+                        row.categories.remove(col_id);
+                    }
+                    false => return,
+                },
+                // An empty text clears a date
+                _ if matches!(col_cfg.col_type, ColumnType::Date(_)) => match parse_date(&text) {
+                    Some(date) => {
+                        row.col_data.insert(col_id.clone(), date);
+                    }
+                    None if text.trim().is_empty() => {
+                        row.col_data.remove(col_id);
+                    }
+                    None => return,
+                },
+                Some((x, None)) => match row.quantities.get_mut(col_id) {
+                    Some(quantity) => quantity.amount = x,
+                    None => {
+                        row.col_data.insert(col_id.clone(), x);
+                    }
+                },
+                Some((x, Some(unit))) if unit == col_cfg.unit => {
+                    row.quantities.remove(col_id);
+                    row.col_data.insert(col_id.clone(), x);
+                }
+                Some((x, Some(unit)))
+                    if conversion_factor(
+                        unit,
+                        &col_cfg.unit,
+                        &self.doc.tree_table.exchange_rates,
+                    )
+                    .is_some() =>
+                {
+                    let quantity = Quantity {
+                        amount: x,
+                        unit: unit.to_owned(),
+                    };
+                    row.quantities.insert(col_id.clone(), quantity);
+                }
+                _ => return,
+            }
+            self.doc.mark_modified(&cursor.row_path);
+        }
+    }
+
+    fn set_selected_enabled(&mut self, enabled: bool) {
+        for row_path in self.doc.nav.selected_rows.clone() {
+            // The root is always enabled
+            if row_path.is_empty() {
+                continue;
+            }
+            if let Some(row) = self.doc.tree_table.root_row.get_row_mut(&row_path) {
+                row.enabled = enabled;
+                self.doc.mark_modified(&row_path);
+            }
+        }
+    }
+
+    fn set_selected_multiplier(&mut self, multiplier: f64) {
+        for row_path in self.doc.nav.selected_rows.clone() {
+            // The root is always counted once
+            if row_path.is_empty() {
+                continue;
+            }
+            if let Some(row) = self.doc.tree_table.root_row.get_row_mut(&row_path) {
+                row.multiplier = multiplier;
+                row.multiplier_parameter = None;
+                self.doc.mark_modified(&row_path);
+            }
+        }
+    }
+
+    fn set_selected_multiplier_parameter(&mut self, parameter: Option<String>) {
+        for row_path in self.doc.nav.selected_rows.clone() {
+            if row_path.is_empty() {
+                continue;
+            }
+            if let Some(row) = self.doc.tree_table.root_row.get_row_mut(&row_path) {
+                row.multiplier_parameter = parameter.clone();
+                self.doc.mark_modified(&row_path);
+            }
+        }
+    }
+
+    // Sets the value of the given column for all selected leaf rows
+    fn set_selected_value(&mut self, col_idx: usize, value: f64) {
+        let Some(col_cfg) = self.doc.tree_table.column_configs.get(col_idx) else {
+            return;
+        };
+        if !col_cfg.col_type.is_amount() {
+            return;
+        }
+
+        let col_id = col_cfg.id.clone();
+        for row_path in self.doc.nav.selected_rows.clone() {
+            if let Some(row) = self.doc.tree_table.root_row.get_row_mut(&row_path) {
+                if row.children.is_empty() {
+                    row.quantities.remove(&col_id);
+                    row.col_data.insert(col_id.clone(), value);
+                    self.doc.mark_modified(&row_path);
+                }
+            }
+        }
+    }
+
+    // Removes the selected rows (and their subtrees) and returns them in tree order
+    fn take_selected_rows(&mut self) -> Vec<RowData> {
+        let roots = selection_roots(&self.doc.nav.selected_rows);
+
+        // Remove from the back, so that the remaining paths stay valid
+        let mut rows: Vec<RowData> = roots
+            .iter()
+            .rev()
+            .filter_map(|row_path| self.doc.tree_table.root_row.remove_row(row_path))
+            .collect();
+        rows.reverse();
+
+        self.doc.nav.clear_selection();
+        self.doc.nav.cursor = None;
+        self.doc.nav.edit_buffer = None;
+        rows
+    }
+
+    fn delete_selected(&mut self) {
+        if !self.take_selected_rows().is_empty() {
+            self.doc.mark_all_modified();
+        }
+    }
+
+    /// Moves the selected rows into a new group, which is placed at the position of the first one.
+    fn group_selected(&mut self) {
+        let Some(first_path) = selection_roots(&self.doc.nav.selected_rows)
+            .first()
+            .cloned()
+        else {
+            return;
+        };
+        let children = self.take_selected_rows();
+
+        // All other removed rows come after the first one, so its parent path is still valid
+        let (idx, parent_path) = first_path.split_last().unwrap();
+        let parent = self
+            .doc
+            .tree_table
+            .root_row
+            .get_row_mut(parent_path)
+            .unwrap();
+        parent.children.insert(
+            *idx,
+            RowData {
+                children,
+                edit_name: true,
+                ..RowData::default()
+            },
+        );
+        self.doc.mark_all_modified();
+    }
+
+    // Saves the selected row as a new template, or updates the template it is linked to
+    fn save_selected_as_template(&mut self) {
+        let [row_path] = &self.doc.nav.selected_rows[..] else {
+            return;
+        };
+        let Some(row) = self.doc.tree_table.root_row.get_row(row_path) else {
+            return;
+        };
+
+        let content = row.to_template(&self.doc.tree_table.editable_columns());
+        let linked = (self.library.templates.iter_mut())
+            .find(|template| Some(&template.id) == row.template_id.as_ref());
+        match linked {
+            Some(template) => template.row = content,
+            None => self.library.templates.push(Template {
+                id: Uuid::new_v4().to_string(),
+                row: content,
+            }),
+        }
+        self.library_changed();
+    }
+
+    // Writes the library and updates the linked rows of all open documents
+    fn library_changed(&mut self) {
+        if let Some(err) = &self.library_error {
+            log::warn!("The template library isn't saved, it could not be read: {err}");
+        } else if let Err(err) = self.library.save() {
+            log::warn!("Could not save the template library: {err}");
+        }
+        self.doc.check_templates(&self.library);
+        for doc in self.documents.iter_mut() {
+            doc.check_templates(&self.library);
+        }
+    }
+
+    // Lists the templates of the library, which are inserted below the row under the cursor
+    fn templates_panel(&mut self, ctx: &egui::Context) {
+        let mut inserted = None;
+        let mut removed = None;
+
+        egui::SidePanel::right("templates_panel").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Templates");
+                if ui.button("✖").clicked() {
+                    self.show_templates = false;
+                }
+            });
+            ui.checkbox(&mut self.link_templates, "Keep inserted rows linked");
+            if let Some(err) = &self.library_error {
+                ui.colored_label(
+                    ui.visuals().error_fg_color,
+                    format!("The library file could not be read, changes aren't saved: {err}"),
+                );
+            }
+
+            let cursor_path = (self.doc.nav.cursor.as_ref()).map_or(vec![], |c| c.row_path.clone());
+            let target =
+                (self.doc.tree_table.root_row.get_row(&cursor_path)).map_or("", |row| &row.name);
+            ui.label(format!("Inserts below: {target}"));
+            ui.separator();
+
+            if self.library.templates.is_empty() {
+                ui.label("Select a row and save it as a template.");
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (idx, template) in self.library.templates.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        if ui.button("Insert").clicked() {
+                            inserted = Some((idx, cursor_path.clone()));
+                        }
+                        if ui.button("🗑").on_hover_text("Remove template").clicked() {
+                            removed = Some(idx);
+                        }
+                        ui.label(&template.row.name);
+                    });
+                }
+            });
+        });
+
+        if let Some((idx, row_path)) = inserted {
+            let row = RowData::from_template(&self.library.templates[idx], self.link_templates);
+            if let Some(parent) = self.doc.tree_table.root_row.get_row_mut(&row_path) {
+                parent.children.push(row);
+                parent.expanded = true;
+                self.doc.mark_all_modified();
+            }
+        }
+        // Linked rows keep the content of a removed template
+        if let Some(idx) = removed {
+            self.library.templates.remove(idx);
+            self.library_changed();
+        }
+    }
+
+    fn selection_bar(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} rows selected:",
+                self.doc.nav.selected_rows.len()
+            ));
+
+            if ui.button("Enable").clicked() {
+                self.set_selected_enabled(true);
+            }
+            if ui.button("Disable").clicked() {
+                self.set_selected_enabled(false);
+            }
+            ui.separator();
+
+            ui.add(
+                egui::DragValue::new(&mut self.bulk_edit.multiplier)
+                    .speed(1.0)
+                    .suffix("x"),
+            );
+            if ui.button("Set multiplier").clicked() {
+                self.set_selected_multiplier(self.bulk_edit.multiplier);
+            }
+            if !self.doc.tree_table.parameters.is_empty() {
+                let mut linked = None;
+                egui::ComboBox::from_id_source("bulk_edit_parameter")
+                    .selected_text("Link to parameter")
+                    .show_ui(ui, |ui| {
+                        if ui.selectable_label(false, "None").clicked() {
+                            linked = Some(None);
+                        }
+                        for parameter in self.doc.tree_table.parameters.iter() {
+                            if ui.selectable_label(false, &parameter.name).clicked() {
+                                linked = Some(Some(parameter.name.clone()));
+                            }
+                        }
+                    });
+                if let Some(parameter) = linked {
+                    self.set_selected_multiplier_parameter(parameter);
+                }
+            }
+            ui.separator();
+
+            let amount_columns: Vec<(usize, String)> = self
+                .doc
+                .tree_table
+                .column_configs
+                .iter()
+                .enumerate()
+                .filter(|(_, cfg)| cfg.col_type.is_amount())
+                .map(|(i, cfg)| (i, cfg.caption.clone()))
+                .collect();
+            if !amount_columns.is_empty() {
+                let selected_caption = amount_columns
+                    .iter()
+                    .find(|(i, _)| *i == self.bulk_edit.col_idx)
+                    .map(|(_, caption)| caption.clone())
+                    .unwrap_or_default();
+                egui::ComboBox::from_id_source("bulk_edit_column")
+                    .selected_text(selected_caption)
+                    .show_ui(ui, |ui| {
+                        for (i, caption) in amount_columns.iter() {
+                            ui.selectable_value(&mut self.bulk_edit.col_idx, *i, caption);
+                        }
+                    });
+                ui.add(egui::DragValue::new(&mut self.bulk_edit.value).speed(1.0));
+                if ui.button("Set value").clicked() {
+                    self.set_selected_value(self.bulk_edit.col_idx, self.bulk_edit.value);
+                }
+                ui.separator();
+            }
+
+            let selected_rows = self.doc.nav.selected_rows.clone();
+            if let [row_path] = &selected_rows[..] {
+                let row = self.doc.tree_table.root_row.get_row(row_path);
+                let template_id = row.and_then(|row| row.template_id.clone());
+                let linked =
+                    (template_id.as_ref()).is_some_and(|id| self.library.find(id).is_some());
+                let text = if linked {
+                    "Update template"
+                } else {
+                    "Save as template"
+                };
+                if ui.button(text).clicked() {
+                    self.save_selected_as_template();
+                }
+                if template_id.is_some() && ui.button("Unlink template").clicked() {
+                    if let Some(row) = self.doc.tree_table.root_row.get_row_mut(row_path) {
+                        row.template_id = None;
+                    }
+                    self.doc.mark_changed();
+                }
+                ui.separator();
+            }
+
+            if ui.button("Group").clicked() {
+                self.group_selected();
+            }
+            if ui.button("🗑").clicked() {
+                self.delete_selected();
+            }
+            if ui.button("Clear selection").clicked() {
+                self.doc.nav.clear_selection();
+            }
+        });
+    }
+
+    // Header cell of a data column: drag the caption to reorder, drag the handle to resize
+    fn column_header(&mut self, ui: &mut Ui, col_idx: usize) -> Option<(usize, usize)> {
+        let mut moved_column = None;
+        let mismatch = self.doc.tree_table.unit_mismatch(col_idx);
+        let cfg = &mut self.doc.tree_table.column_configs[col_idx];
+        let mut caption = format!("{} ({})", cfg.caption, cfg.unit);
+        if mismatch.is_some() {
+            caption = format!("⚠ {caption}");
+        }
+
+        let resp = ui.dnd_drag_source(Id::new(("column_header", col_idx)), col_idx, |ui| {
+            let label = ui.add(egui::Label::new(caption).sense(Sense::click()));
+            match &mismatch {
+                Some(mismatch) => label.on_hover_text(mismatch),
+                None => label,
+            }
+        });
+        if resp.inner.double_clicked() {
+            self.edit_column_idx = Some(col_idx);
+        }
+        if let Some(from_idx) = resp.response.dnd_release_payload::<usize>() {
+            moved_column = Some((*from_idx, col_idx));
+        }
+
+        // The width is a view preference, resizing doesn't modify the document
+        ui.with_layout(egui::Layout::right_to_left(Align::Center), |ui| {
+            let handle = ui
+                .add(egui::Label::new("⋮").sense(Sense::drag()))
+                .on_hover_cursor(CursorIcon::ResizeHorizontal);
+            if handle.dragged() {
+                cfg.width = (cfg.width + handle.drag_delta().x).max(MIN_COLUMN_WIDTH);
+            }
+        });
+
+        moved_column
+    }
+
+    /// Shows the table with a sticky header row and a frozen name column.
+    ///
+    /// Only the lines within the viewport are laid out.
+    fn show_table(&mut self, ui: &mut Ui) {
+        let spacing = ui.spacing().item_spacing;
+        let row_height = ui.spacing().interact_size.y + spacing.y;
+
+        if self.doc.table_lines_outdated {
+            self.doc.table_lines = self
+                .doc
+                .tree_table
+                .root_row
+                .table_lines(self.doc.filter.as_ref());
+            self.doc.table_lines_outdated = false;
+        }
+        let lines = std::mem::take(&mut self.doc.table_lines);
+        // The span of the Gantt bars
+        let root_values = self.doc.tree_table.root_row.col_data.clone();
+
+        let (columns, x) =
+            TableLayout::shown_columns(&self.doc.tree_table.column_configs, spacing.x);
+
+        let content_size = vec2(
+            self.name_column_width + x + BUTTON_COLUMN_WIDTH,
+            row_height * (lines.len() + 1) as f32,
+        );
+
+        let mut line_actions = vec![];
+        let mut moved_column = None;
+
+        egui::ScrollArea::both()
+            .auto_shrink(false)
+            .show_viewport(ui, |ui, viewport| {
+                let (content_rect, _) = ui.allocate_exact_size(content_size, Sense::hover());
+                let view =
+                    Rect::from_min_size(content_rect.min + viewport.min.to_vec2(), viewport.size())
+                        .intersect(ui.clip_rect());
+                let layout = TableLayout {
+                    origin: content_rect.min,
+                    view,
+                    split: view.min + vec2(self.name_column_width, row_height),
+                    row_height,
+                    columns,
+                    button_x: x,
+                };
+
+                if self.doc.nav.scroll_to_cursor {
+                    self.doc.nav.scroll_to_cursor = false;
+                    self.scroll_to_cursor(ui, &layout, &lines);
+                }
+
+                let first_line = (viewport.min.y / row_height).floor().max(0.0) as usize;
+                let last_line = ((viewport.max.y / row_height).ceil() as usize).min(lines.len());
+                let shown_lines = lines.iter().enumerate().take(last_line).skip(first_line);
+                for (line_idx, line) in shown_lines {
+                    let y = layout.origin.y + row_height * (line_idx + 1) as f32;
+                    self.show_table_line(ui, &layout, line, y, &root_values, &mut line_actions);
+                }
+
+                moved_column = self.show_table_header(ui, &layout);
+            });
+        self.doc.table_lines = lines;
+
+        if let Some((from_idx, to_idx)) = moved_column {
+            self.move_column(from_idx, to_idx);
+        }
+
+        for (row_path, action) in line_actions {
+            match action {
+                Action::Modified => {
+                    self.doc.mark_modified(&row_path);
+                }
+                Action::Remove => {
+                    if self.doc.tree_table.root_row.remove_row(&row_path).is_some() {
+                        // Removing a children, means that something changed!
+                        self.doc.nav.clear_selection();
+                        self.doc.mark_modified(&row_path[..row_path.len() - 1]);
+                    }
+                }
+                Action::AddChild => {
+                    let row = self.doc.tree_table.root_row.get_row_mut(&row_path).unwrap();
+                    row.add_child(&self.doc.tree_table.column_configs);
+                    self.doc.mark_modified(&row_path);
+                }
+                Action::Expanded => {
+                    self.doc.table_lines_outdated = true;
+                }
+            }
+        }
+
+        if let Some((row_path, modifiers)) = self.doc.nav.clicked_row.take() {
+            let visible_paths =
+                (self.doc.tree_table.root_row).visible_paths(self.doc.filter.as_ref());
+            self.doc.nav.click_row(row_path, modifiers, &visible_paths);
+        }
+    }
+
+    // Moves a column to another position, the cursor and the column dialog stay on it
+    fn move_column(&mut self, from_idx: usize, to_idx: usize) {
+        let column_configs = &mut self.doc.tree_table.column_configs;
+        if from_idx == to_idx || to_idx >= column_configs.len() {
+            return;
+        }
+        let cfg = column_configs.remove(from_idx);
+        column_configs.insert(to_idx, cfg);
+
+        let moved = |idx: usize| match idx {
+            _ if idx == from_idx => to_idx,
+            _ if from_idx < idx && idx <= to_idx => idx - 1,
+            _ if to_idx <= idx && idx < from_idx => idx + 1,
+            _ => idx,
+        };
+        // The cursor counts the name column as well
+        if let Some(cursor) = self.doc.nav.cursor.as_mut().filter(|c| c.col_idx > 0) {
+            cursor.col_idx = moved(cursor.col_idx - 1) + 1;
         }
+        self.edit_column_idx = self.edit_column_idx.map(moved);
+        self.bulk_edit.col_idx = moved(self.bulk_edit.col_idx);
+        self.doc.mark_changed();
     }
-}
 
-impl Default for TreeTablesApp {
-    fn default() -> Self {
-        Self {
-            filename: "unnamed.tt".to_owned(),
-            file_modified: true,
-            tree_table: TreeTable {
-                title_text: "Tree Tables".to_owned(),
-                column_configs: vec![
-                    ColumnConfig {
-                        id: "2387c84a-2c68-405e-a342-d94a1dde6408".to_owned(),
-                        caption: "Materialkosten".to_owned(),
-                        unit: "€".to_owned(),
-                        col_type: ColumnType::Number,
-                    },
-                    ColumnConfig {
-                        id: "94869fe6-c736-4c88-be7f-8084679d78fc".to_owned(),
-                        caption: "Arbeitszeit".to_owned(),
-                        unit: "h".to_owned(),
-                        col_type: ColumnType::Number,
-                    },
-                    ColumnConfig {
-                        id: "5aafbaab-6c03-4e8f-9fc4-cfb66ed2fb16".to_owned(), // Uuid::new_v4().to_string(),
-                        caption: "Verkaufspreis".to_owned(),
-                        unit: "€".to_owned(),
-                        col_type: ColumnType::MultiplyByFactor(
-                            "2387c84a-2c68-405e-a342-d94a1dde6408".to_owned(),
-                            100.0,
-                        ),
-                    },
-                ],
-
-                root_row: RowData {
-                    name: "∑".to_owned(),
-                    col_data: HashMap::from([]),
-                    children: vec![RowData {
-                        name: "A".to_owned(),
-                        col_data: HashMap::from([(
-                            "2387c84a-2c68-405e-a342-d94a1dde6408".to_owned(),
-                            1.0,
-                        )]),
-                        children: vec![],
-                        expanded: false,
-                        edit_name: false,
-                        enabled: true,
-                        multiplier: 1.0,
-                    }],
-                    expanded: false,
-                    edit_name: false,
-                    enabled: true,
-                    multiplier: 1.0,
-                },
-            },
-            edit_title_text: false,
-            save_shortcut: Shortcut::new(
-                Some(egui::KeyboardShortcut::new(
-                    egui::Modifiers::COMMAND,
-                    egui::Key::S,
-                )),
-                None,
-            ),
-            edit_column_idx: None,
-            close_requested: false,
-            show_decimals: false,
+    // Scrolls the cell under the cursor into view, even if its line isn't laid out yet
+    fn scroll_to_cursor(&self, ui: &Ui, layout: &TableLayout, lines: &[TableLine]) {
+        let Some(cursor) = self.doc.nav.cursor.as_ref() else {
+            return;
+        };
+        let line_idx = lines.iter().position(|line| match line {
+            TableLine::Row { row_path, .. } => *row_path == cursor.row_path,
+            TableLine::AddChild { .. } => false,
+        });
+        let Some(line_idx) = line_idx else {
+            return;
+        };
+        ui.scroll_to_rect(layout.cell_scroll_rect(line_idx, cursor.col_idx), None);
+    }
+
+    fn show_table_line(
+        &mut self,
+        ui: &Ui,
+        layout: &TableLayout,
+        line: &TableLine,
+        y: f32,
+        root_values: &BTreeMap<ColumnID, f64>,
+        line_actions: &mut Vec<(Vec<usize>, Action)>,
+    ) {
+        let name_rect = Rect::from_min_max(
+            pos2(layout.view.min.x, y),
+            pos2(layout.split.x, y + layout.row_height),
+        );
+        let name_clip = layout.name_clip();
+        let body_clip = layout.body_clip();
+
+        match line {
+            TableLine::Row {
+                row_path,
+                parent_enabled,
+            } => {
+                let row = self.doc.tree_table.root_row.get_row_mut(row_path).unwrap();
+                let row_id = Id::new(&row.id);
+
+                for (col_idx, x, width) in layout.columns.iter() {
+                    let rect = layout.body_rect(*x, y, *width);
+                    if !rect.intersects(body_clip) {
+                        continue;
+                    }
+
+                    let col_cfg = &self.doc.tree_table.column_configs[*col_idx];
+                    let id = row_id.with(col_idx);
+                    let action = show_cell(ui, rect, body_clip, id, |ui| {
+                        row.render_cell(
+                            ui,
+                            *col_idx,
+                            col_cfg,
+                            *parent_enabled,
+                            self.show_decimals,
+                            row_path,
+                            &mut self.doc.nav,
+                            &self.doc.tree_table.exchange_rates,
+                            root_values,
+                        )
+                    });
+                    if let Some(action) = action {
+                        line_actions.push((row_path.clone(), action));
+                    }
+                }
+
+                // Remove row button
+                let button_rect = layout.body_rect(layout.button_x, y, BUTTON_COLUMN_WIDTH);
+                show_cell(ui, button_rect, body_clip, row_id.with("remove"), |ui| {
+                    if ui.button("🗑").clicked() {
+                        line_actions.push((row_path.clone(), Action::Remove));
+                    }
+                });
+
+                let action = show_cell(ui, name_rect, name_clip, row_id.with("name"), |ui| {
+                    row.render_name(
+                        ui,
+                        *parent_enabled,
+                        self.show_decimals,
+                        row_path,
+                        &mut self.doc.nav,
+                    )
+                });
+                if let Some(action) = action {
+                    line_actions.push((row_path.clone(), action));
+                }
+            }
+            TableLine::AddChild { row_path } => {
+                let row = self.doc.tree_table.root_row.get_row(row_path).unwrap();
+                let id = Id::new(&row.id).with("add");
+                show_cell(ui, name_rect, name_clip, id, |ui| {
+                    ui.add_space(INDENT_WIDTH * (row_path.len() + 1) as f32);
+                    if ui.button("+").clicked() {
+                        line_actions.push((row_path.clone(), Action::AddChild));
+                    }
+                });
+            }
         }
     }
-}
 
-impl TreeTablesApp {
-    /// Called once before the first frame.
-    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        // This is also where you can customize the look and feel of egui using
-        // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
+    // Shows the sticky header row, returns the (from, to) indices if a column was moved
+    fn show_table_header(&mut self, ui: &Ui, layout: &TableLayout) -> Option<(usize, usize)> {
+        let header_y = layout.view.min.y;
+        let header_clip = layout.header_clip();
 
-        // Load previous app state (if any).
-        // Note that you must enable the `persistence` feature for this to work.
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+        let mut moved_column = None;
+        for (col_idx, x, width) in layout.columns.iter() {
+            let rect = layout.body_rect(*x, header_y, *width);
+            if rect.intersects(header_clip) {
+                let id = Id::new(("column_header_cell", col_idx));
+                if let Some(moved) = show_cell(ui, rect, header_clip, id, |ui| {
+                    self.column_header(ui, *col_idx)
+                }) {
+                    moved_column = Some(moved);
+                }
+            }
+        }
+
+        let button_rect = layout.body_rect(layout.button_x, header_y, BUTTON_COLUMN_WIDTH);
+        show_cell(ui, button_rect, header_clip, Id::new("add_column"), |ui| {
+            if ui.button("+").clicked() {
+                self.edit_column_idx = Some(self.doc.tree_table.column_configs.len());
+
+                self.doc
+                    .tree_table
+                    .column_configs
+                    .push(ColumnConfig::default());
+                self.doc.mark_all_modified();
+            }
+        });
+
+        // The top left corner with the handle to resize the name column
+        let corner_rect = Rect::from_min_max(layout.view.min, layout.split);
+        show_cell(ui, corner_rect, layout.view, Id::new("name_header"), |ui| {
+            ui.with_layout(egui::Layout::right_to_left(Align::Center), |ui| {
+                let handle = ui
+                    .add(egui::Label::new("⋮").sense(Sense::drag()))
+                    .on_hover_cursor(CursorIcon::ResizeHorizontal);
+                if handle.dragged() {
+                    self.name_column_width =
+                        (self.name_column_width + handle.drag_delta().x).max(MIN_COLUMN_WIDTH);
+                }
+            });
+        });
+
+        moved_column
+    }
+
+    fn handle_undo_keys(&mut self, ctx: &egui::Context) {
+        // Text edits have their own undo
+        if ctx.memory(|m| m.focus().is_some()) {
+            return;
+        }
+
+        // Check redo first, because undo would also match it logically
+        let (redo, undo) = ctx.input_mut(|i| {
+            (
+                i.consume_shortcut(&REDO_SHORTCUT),
+                i.consume_shortcut(&UNDO_SHORTCUT),
+            )
+        });
+        if redo {
+            self.doc.redo();
+        } else if undo {
+            self.doc.undo();
+        }
+    }
+
+    /// Handles the keyboard navigation and editing of the table cells.
+    fn handle_table_keys(&mut self, ctx: &egui::Context) {
+        let Some(mut cursor) = self.doc.nav.cursor.clone() else {
+            return;
+        };
+
+        // The row under the cursor might have been removed in the meantime
+        if self
+            .doc
+            .tree_table
+            .root_row
+            .get_row(&cursor.row_path)
+            .is_none()
+        {
+            self.doc.nav.cursor = None;
+            self.doc.nav.edit_buffer = None;
+            return;
+        }
+
+        if self.doc.nav.edit_buffer.is_some() {
+            let (commit, cancel, move_left, move_right) = ctx.input_mut(|i| {
+                (
+                    i.consume_key(Modifiers::NONE, Key::Enter),
+                    i.consume_key(Modifiers::NONE, Key::Escape),
+                    i.consume_key(Modifiers::SHIFT, Key::Tab),
+                    i.consume_key(Modifiers::NONE, Key::Tab),
+                )
+            });
+
+            if cancel {
+                self.doc.nav.edit_buffer = None;
+            } else if commit || move_left || move_right {
+                self.commit_cell_edit(&cursor);
+                if move_left || move_right {
+                    self.move_cursor(0, if move_left { -1 } else { 1 });
+                    self.doc.nav.drop_focus = true;
+                }
+            }
+            return;
+        }
+
+        // Don't steal the keys from other widgets, e.g. the title text edit
+        if ctx.memory(|m| m.focus().is_some()) {
+            return;
+        }
+
+        let (add_child, delete_row, toggle_expand) = ctx.input_mut(|i| {
+            (
+                i.consume_shortcut(&ADD_CHILD_SHORTCUT),
+                i.consume_shortcut(&DELETE_ROW_SHORTCUT),
+                i.consume_shortcut(&TOGGLE_EXPAND_SHORTCUT),
+            )
+        });
+
+        if add_child {
+            let row = self
+                .doc
+                .tree_table
+                .root_row
+                .get_row_mut(&cursor.row_path)
+                .unwrap();
+            row.add_child(&self.doc.tree_table.column_configs);
+            row.expanded = true;
+            row.children.last_mut().unwrap().edit_name = false;
+            let child_idx = row.children.len() - 1;
+
+            self.doc.mark_modified(&cursor.row_path);
+            cursor.row_path.push(child_idx);
+            cursor.col_idx = 0;
+            self.doc.nav.cursor = Some(cursor);
+            self.doc.nav.edit_buffer = Some("".to_owned());
+            return;
+        }
+
+        if delete_row {
+            if self
+                .doc
+                .tree_table
+                .root_row
+                .remove_row(&cursor.row_path)
+                .is_some()
+            {
+                self.doc.nav.clear_selection();
+
+                // Continue with the previous sibling or the parent
+                let idx = cursor.row_path.pop().unwrap();
+                self.doc.mark_modified(&cursor.row_path);
+                if idx > 0 {
+                    cursor.row_path.push(idx - 1);
+                }
+                self.doc.nav.cursor = Some(cursor);
+            }
+            return;
+        }
+
+        if toggle_expand {
+            let row = self
+                .doc
+                .tree_table
+                .root_row
+                .get_row_mut(&cursor.row_path)
+                .unwrap();
+            row.expanded = !row.expanded;
+            self.doc.table_lines_outdated = true;
+            return;
         }
 
-        Default::default()
+        let (up, down, left, right, shift_tab, tab, enter, escape, typed_text) =
+            ctx.input_mut(|i| {
+                let typed_text = i.events.iter().find_map(|e| match e {
+                    Event::Text(t) if t.chars().all(|c| c.is_ascii_digit()) => Some(t.clone()),
+                    _ => None,
+                });
+                (
+                    i.consume_key(Modifiers::NONE, Key::ArrowUp),
+                    i.consume_key(Modifiers::NONE, Key::ArrowDown),
+                    i.consume_key(Modifiers::NONE, Key::ArrowLeft),
+                    i.consume_key(Modifiers::NONE, Key::ArrowRight),
+                    i.consume_key(Modifiers::SHIFT, Key::Tab),
+                    i.consume_key(Modifiers::NONE, Key::Tab),
+                    i.consume_key(Modifiers::NONE, Key::Enter)
+                        || i.consume_key(Modifiers::NONE, Key::F2),
+                    i.consume_key(Modifiers::NONE, Key::Escape),
+                    typed_text,
+                )
+            });
+
+        if up || down || left || right || shift_tab || tab {
+            let row_delta = down as i32 - up as i32;
+            let col_delta = (right || tab) as i32 - (left || shift_tab) as i32;
+            self.move_cursor(row_delta, col_delta);
+            self.doc.nav.drop_focus = shift_tab || tab;
+        } else if enter {
+            self.doc.nav.edit_buffer = self.cell_edit_text(&cursor);
+        } else if let Some(text) = typed_text {
+            if self.cell_edit_text(&cursor).is_some() {
+                self.doc.nav.edit_buffer = Some(text);
+            }
+        } else if escape {
+            self.doc.nav.cursor = None;
+            self.doc.nav.clear_selection();
+        }
     }
 }
 
@@ -476,6 +4874,13 @@ impl ExpandButton for Ui {
 impl eframe::App for TreeTablesApp {
     /// Called by the frame work to save state before shutdown.
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        let mut expanded_rows = std::mem::take(&mut self.expanded_rows);
+        let mut column_widths = std::mem::take(&mut self.column_widths);
+        for doc in self.all_documents() {
+            doc.store_view_state(&mut expanded_rows, &mut column_widths);
+        }
+        self.expanded_rows = expanded_rows;
+        self.column_widths = column_widths;
         eframe::set_value(storage, eframe::APP_KEY, self);
     }
 
@@ -487,16 +4892,21 @@ impl eframe::App for TreeTablesApp {
         // Show a confirmation dialog when the close event is detected
         if ctx.input(|i| i.viewport().close_requested()) {
             egui::CentralPanel::default().show(ctx, |_ui| {
-                if self.file_modified {
+                if self.all_documents().any(|doc| doc.file_modified) {
                     ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
                 }
                 self.close_requested = true;
             });
         }
 
-        self.tree_table
-            .root_row
-            .update(&self.tree_table.column_configs);
+        self.handle_undo_keys(ctx);
+        self.handle_table_keys(ctx);
+
+        self.doc.tree_table.update();
+
+        if !cfg!(target_arch = "wasm32") {
+            self.autosave(ctx);
+        }
 
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
@@ -506,6 +4916,34 @@ impl eframe::App for TreeTablesApp {
                 let is_web = cfg!(target_arch = "wasm32");
                 if !is_web {
                     ui.menu_button("File", |ui| {
+                        ui.menu_button("Open Recent", |ui| {
+                            if self.recent_files.is_empty() {
+                                ui.label("No recent files");
+                            }
+                            for filename in self.recent_files.clone() {
+                                if ui.button(&filename).clicked() {
+                                    self.open_document(&filename);
+                                    ui.close_menu();
+                                }
+                            }
+                            ui.separator();
+                            ui.checkbox(
+                                &mut self.reopen_last_document,
+                                "Reopen the last document on startup",
+                            );
+                        });
+                        ui.menu_button("Autosave", |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Interval:");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.autosave_interval)
+                                        .clamp_range(0..=3600)
+                                        .suffix(" s"),
+                                );
+                            });
+                            ui.label("An interval of 0 turns the autosave off.");
+                            ui.checkbox(&mut self.autosave_to_file, "Save directly to the file");
+                        });
                         if ui.button("Quit").clicked() {
                             ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                         }
@@ -513,10 +4951,107 @@ impl eframe::App for TreeTablesApp {
                     ui.add_space(16.0);
                 }
 
+                ui.menu_button("Edit", |ui| {
+                    let can_undo = self.doc.changed || !self.doc.undo_stack.is_empty();
+                    if ui
+                        .add_enabled(can_undo, egui::Button::new("Undo"))
+                        .clicked()
+                    {
+                        self.doc.undo();
+                        ui.close_menu();
+                    }
+                    let can_redo = !self.doc.changed && !self.doc.redo_stack.is_empty();
+                    if ui
+                        .add_enabled(can_redo, egui::Button::new("Redo"))
+                        .clicked()
+                    {
+                        self.doc.redo();
+                        ui.close_menu();
+                    }
+                });
+                ui.add_space(16.0);
+
+                ui.menu_button("Compare", |ui| self.compare_menu(ui));
+                ui.add_space(16.0);
+
+                ui.menu_button("View", |ui| {
+                    if ui.button("Expand all").clicked() {
+                        self.doc.tree_table.root_row.expand_to_depth(None);
+                        self.doc.table_lines_outdated = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("Collapse all").clicked() {
+                        self.doc.tree_table.root_row.expand_to_depth(Some(0));
+                        self.doc.table_lines_outdated = true;
+                        ui.close_menu();
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Expand to level").clicked() {
+                            self.doc
+                                .tree_table
+                                .root_row
+                                .expand_to_depth(Some(self.expand_depth));
+                            self.doc.table_lines_outdated = true;
+                            ui.close_menu();
+                        }
+                        ui.add(egui::DragValue::new(&mut self.expand_depth).clamp_range(0..=99));
+                    });
+                    ui.separator();
+                    ui.menu_button("Columns", |ui| {
+                        let mut changed = false;
+                        for cfg in self.doc.tree_table.column_configs.iter_mut() {
+                            let mut visible = !cfg.hidden;
+                            if ui.checkbox(&mut visible, cfg.caption.clone()).changed() {
+                                cfg.hidden = !visible;
+                                changed = true;
+                            }
+                        }
+                        if changed {
+                            self.doc.mark_changed();
+                        }
+                    });
+                    if ui.button("Parameters").clicked() {
+                        self.show_parameters = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("Exchange rates").clicked() {
+                        self.show_exchange_rates = true;
+                        ui.close_menu();
+                    }
+                    if ui.button("Templates").clicked() {
+                        self.show_templates = true;
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        let resp = ui.text_edit_singleline(&mut self.reveal_text);
+                        let enter = resp.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                        if ui.button("Reveal").clicked() || enter {
+                            let text = self.reveal_text.clone();
+                            self.reveal_row(&text);
+                            ui.close_menu();
+                        }
+                    });
+                });
+                ui.add_space(16.0);
+
                 egui::widgets::global_dark_light_mode_buttons(ui);
             });
         });
 
+        egui::TopBottomPanel::bottom("bottom_panel").show(ctx, |ui| {
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.label(
+                    RichText::new(format!("tree-tables v{VERSION}")).text_style(TextStyle::Small),
+                );
+                egui::warn_if_debug_build(ui);
+            });
+        });
+
+        if self.show_templates {
+            self.templates_panel(ctx);
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             // ui.label("A simple keybind:");
             // let response = ui.add(Keybind::new(&mut self.save_shortcut, "example_keybind"));
@@ -529,31 +5064,39 @@ impl eframe::App for TreeTablesApp {
                     ui.label(
                         "You still have unsaved changes. Do you want to save them before you quit?",
                     );
+                    for doc in self.all_documents().filter(|doc| doc.file_modified) {
+                        ui.label(RichText::new(&doc.filename).monospace());
+                    }
                     ui.horizontal(|ui| {
                         if ui.button("Yes, save!").clicked() {
-                            self.tree_table.save_to_file(self.filename.as_str());
-                            self.file_modified = false;
+                            for doc in self.all_documents_mut().filter(|doc| doc.file_modified) {
+                                doc.save();
+                            }
                             ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                         }
                         if ui.button("No, revert all changes and quit!").clicked() {
-                            self.file_modified = false;
+                            for doc in self.all_documents_mut() {
+                                doc.file_modified = false;
+                                autosave::remove_recovery(&doc.filename, &doc.id);
+                            }
                             ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                         }
                     });
                 });
             };
 
+            self.tab_bar(ui);
+
             // let keybind_text = self.save_shortcut.format(&egui::ModifierNames::NAMES, true);
             if ctx.input_mut(|i| self.save_shortcut.pressed(i)) {
-                self.tree_table.save_to_file(self.filename.as_str());
-                self.file_modified = false;
+                self.doc.save();
             }
 
             ui.label(
                 egui::RichText::new(format!(
                     "{}{}",
-                    self.filename,
-                    if self.file_modified {
+                    self.doc.filename,
+                    if self.doc.file_modified {
                         "*".to_owned()
                     } else {
                         "".to_owned()
@@ -568,21 +5111,12 @@ impl eframe::App for TreeTablesApp {
                         .add_filter("Tree-Tables", &VALID_FILE_EXTENSIONS)
                         .pick_file()
                     {
-                        let file_data = std::fs::read_to_string(path.display().to_string())
-                            .expect("Should have been able to read the file");
-
-                        let json_state: TreeTable = serde_json::from_str(file_data.as_str())
-                            .expect("JSON data is corrupted.");
-
-                        self.tree_table = json_state;
-                        self.filename = path.display().to_string();
-                        self.file_modified = false;
+                        self.open_document(&path.display().to_string());
                     }
                 }
 
                 if ui.button("Save").clicked() {
-                    self.tree_table.save_to_file(self.filename.as_str());
-                    self.file_modified = false;
+                    self.doc.save();
                 }
 
                 if ui.button("Save as").clicked() {
@@ -594,88 +5128,97 @@ impl eframe::App for TreeTablesApp {
                         let mut path = path;
                         path.set_extension("tt");
 
-                        self.filename = path.display().to_string();
-                        self.tree_table.save_to_file(self.filename.as_str());
-                        self.file_modified = false;
+                        autosave::remove_recovery(&self.doc.filename, &self.doc.id);
+                        self.doc.filename = path.display().to_string();
+                        self.doc.save();
+                        self.add_recent_file();
                     }
                 }
-            });
 
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                // The central panel the region left after adding TopPanel's and SidePanel's
-                if self.edit_title_text == false {
-                    if ui
-                        .heading(self.tree_table.title_text.clone())
-                        .double_clicked()
+                if ui.button("Export CSV").clicked() {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("CSV", &["csv"])
+                        .save_file()
                     {
-                        self.edit_title_text = true;
-                    }
-                } else {
-                    let resp = ui.text_edit_singleline(&mut self.tree_table.title_text);
-                    if resp.lost_focus() || resp.clicked_elsewhere() {
-                        self.edit_title_text = false;
+                        let mut path = path;
+                        path.set_extension("csv");
+
+                        let path = path.display().to_string();
+                        (self.doc.tree_table).export_csv(&path, self.export_notes);
                     }
                 }
+                ui.checkbox(&mut self.export_notes, "with notes");
+            });
 
-                egui::Grid::new("table").show(ui, |ui| {
-                    ui.label("");
+            self.scenario_bar(ui);
+            self.filter_bar(ui);
 
-                    // HEADLINE
-                    for (col_idx, cfg) in self.tree_table.column_configs.iter().enumerate() {
-                        let caption = cfg.caption.clone();
-                        let unit = cfg.unit.clone();
-                        ui.horizontal(|ui| {
-                            if ui.label(format!("{caption} ({unit})")).double_clicked() {
-                                self.edit_column_idx = Some(col_idx);
-                            }
-                        });
-                    }
-                    ui.horizontal(|ui| {
-                        ui.add_space(20.0);
-                        if ui.button("+").clicked() {
-                            self.edit_column_idx = Some(self.tree_table.column_configs.len());
+            if !self.doc.nav.selected_rows.is_empty() {
+                self.selection_bar(ui);
+            }
 
-                            self.tree_table.column_configs.push(ColumnConfig::default());
-                        }
-                    });
-                    ui.end_row();
+            // The central panel the region left after adding TopPanel's and SidePanel's
+            if !self.edit_title_text {
+                if ui
+                    .heading(self.doc.tree_table.title_text.clone())
+                    .double_clicked()
+                {
+                    self.edit_title_text = true;
+                }
+            } else {
+                let resp = ui.text_edit_singleline(&mut self.doc.tree_table.title_text);
+                if resp.lost_focus() || resp.clicked_elsewhere() {
+                    self.edit_title_text = false;
+                }
+            }
 
-                    match self.tree_table.root_row.render(
-                        ui,
-                        &self.tree_table.column_configs,
-                        0,
-                        true,
-                        self.show_decimals,
-                    ) {
-                        Some(Action::Modified) => {
-                            self.file_modified = true;
-                        }
-                        Some(Action::Remove) => {}
-                        None => {}
-                    }
-                });
+            self.show_table(ui);
+        });
 
-                ui.separator();
+        self.recovery_window(ctx);
+        self.outdated_templates_window(ctx);
+        self.close_tab_window(ctx);
+        self.compare_window(ctx);
+        self.conflicts_window(ctx);
+        self.scenario_summary_window(ctx);
+        self.parameters_window(ctx);
+        self.exchange_rates_window(ctx);
+        self.row_details_window(ctx);
 
-                ui.with_layout(egui::Layout::bottom_up(egui::Align::RIGHT), |ui| {
-                    egui::warn_if_debug_build(ui);
-                    ui.label(
-                        RichText::new(format!("tree-tables v{VERSION}"))
-                            .text_style(TextStyle::Small),
-                    );
-                    ui.separator();
-                });
+        // A change is finished once the mouse button is released, e.g. after dragging a value,
+        // or once the edited text loses the focus
+        let finished = !ctx.input(|i| i.pointer.any_down()) && !self.doc.editing_text;
+        if self.doc.changed && finished {
+            self.doc.record_undo_step();
+        }
+
+        if self.doc.nav.drop_focus {
+            ctx.memory_mut(|m| {
+                if let Some(id) = m.focus() {
+                    m.surrender_focus(id);
+                }
             });
-        });
+            self.doc.nav.drop_focus = false;
+        }
 
         if self.edit_column_idx.is_some() {
             egui::Window::new("Edit column").show(ctx, |ui| {
-                let column_configs = self.tree_table.column_configs.clone();
+                let column_configs = self.doc.tree_table.column_configs.clone();
+                let parameters = self.doc.tree_table.parameters.clone();
                 let current_column_id = &column_configs
                     .get(self.edit_column_idx.unwrap())
                     .unwrap()
                     .id;
 
+                // Markup columns show the steps of the row under the cursor, or of the root row
+                let cursor_path =
+                    (self.doc.nav.cursor.as_ref()).map_or(vec![], |c| c.row_path.clone());
+                let preview_row = (self.doc.tree_table.root_row.get_row(&cursor_path))
+                    .unwrap_or(&self.doc.tree_table.root_row);
+                let preview_name = preview_row.name.clone();
+                let preview_unit = column_configs[self.edit_column_idx.unwrap()].unit.clone();
+                let preview_results = preview_row.markup_results.get(current_column_id).cloned();
+
                 egui::Grid::new("edit_column_table").show(ui, |ui| {
                     // ui.label("ID");
                     // ui.add_sized(
@@ -693,64 +5236,54 @@ impl eframe::App for TreeTablesApp {
 
                     ui.label("Type:");
                     ui.horizontal(|ui| {
-                        if ui
-                            .selectable_label(
-                                self.tree_table
-                                    .column_configs
-                                    .get_mut(self.edit_column_idx.unwrap())
-                                    .unwrap()
-                                    .col_type
-                                    == ColumnType::Number,
-                                "Number",
-                            )
-                            .clicked()
-                        {
-                            self.tree_table
-                                .column_configs
-                                .get_mut(self.edit_column_idx.unwrap())
-                                .unwrap()
-                                .col_type = ColumnType::Number;
-                        }
-                        if ui
-                            .selectable_label(
-                                self.tree_table
-                                    .column_configs
-                                    .get_mut(self.edit_column_idx.unwrap())
-                                    .unwrap()
-                                    .col_type
-                                    == ColumnType::Text,
-                                "Text",
-                            )
-                            .clicked()
-                        {
-                            self.tree_table
-                                .column_configs
-                                .get_mut(self.edit_column_idx.unwrap())
-                                .unwrap()
-                                .col_type = ColumnType::Text;
-                        }
-                        if ui
-                            .selectable_label(
-                                if let ColumnType::MultiplyByFactor(_id, _factor) = &self
-                                    .tree_table
-                                    .column_configs
-                                    .get_mut(self.edit_column_idx.unwrap())
-                                    .unwrap()
-                                    .col_type
-                                {
-                                    true
-                                } else {
-                                    false
-                                },
+                        // The label, a new column of the type and the unit which it starts with
+                        let col_types = [
+                            ("Number", ColumnType::Number, None),
+                            ("Text", ColumnType::Text, None),
+                            (
                                 "Multiply",
-                            )
-                            .clicked()
-                        {
-                            self.tree_table
-                                .column_configs
-                                .get_mut(self.edit_column_idx.unwrap())
-                                .unwrap()
-                                .col_type = ColumnType::MultiplyByFactor("".to_owned(), 100.0);
+                                ColumnType::MultiplyByFactor("".to_owned(), Factor::Value(100.0)),
+                                None,
+                            ),
+                            ("Sum", ColumnType::RowSum(vec![]), None),
+                            (
+                                "Sum if",
+                                ColumnType::SumIf("".to_owned(), Condition::default()),
+                                None,
+                            ),
+                            (
+                                "% of parent",
+                                ColumnType::ShareOfParent("".to_owned()),
+                                Some("%"),
+                            ),
+                            (
+                                "% of total",
+                                ColumnType::ShareOfTotal("".to_owned()),
+                                Some("%"),
+                            ),
+                            ("Markup", ColumnType::Markup("".to_owned(), vec![]), None),
+                            ("Currency", ColumnType::Currency, None),
+                            ("Date", ColumnType::Date(DateRollup::Earliest), None),
+                            ("Duration", ColumnType::Duration, Some("d")),
+                            (
+                                "Gantt",
+                                ColumnType::Gantt("".to_owned(), "".to_owned()),
+                                None,
+                            ),
+                            ("Boolean", ColumnType::Boolean, None),
+                            ("Enum", ColumnType::Enum(vec![]), None),
+                        ];
+                        let cfg = (self.doc.tree_table.column_configs)
+                            .get_mut(self.edit_column_idx.unwrap())
+                            .unwrap();
+                        for (label, col_type, unit) in col_types {
+                            let selected = discriminant(&cfg.col_type) == discriminant(&col_type);
+                            if ui.selectable_label(selected, label).clicked() && !selected {
+                                cfg.col_type = col_type;
+                                if let Some(unit) = unit {
+                                    cfg.unit = unit.to_owned();
+                                }
+                            }
                         }
                     });
                     ui.end_row();
@@ -760,6 +5293,7 @@ impl eframe::App for TreeTablesApp {
                         [140.0, 20.0],
                         egui::TextEdit::singleline(
                             &mut self
+                                .doc
                                 .tree_table
                                 .column_configs
                                 .get_mut(self.edit_column_idx.unwrap())
@@ -772,6 +5306,7 @@ impl eframe::App for TreeTablesApp {
                     ui.label("Unit:");
                     ui.text_edit_singleline(
                         &mut self
+                            .doc
                             .tree_table
                             .column_configs
                             .get_mut(self.edit_column_idx.unwrap())
@@ -780,7 +5315,21 @@ impl eframe::App for TreeTablesApp {
                     );
                     ui.end_row();
 
+                    ui.label("Hidden:");
+                    ui.checkbox(
+                        &mut self
+                            .doc
+                            .tree_table
+                            .column_configs
+                            .get_mut(self.edit_column_idx.unwrap())
+                            .unwrap()
+                            .hidden,
+                        "",
+                    );
+                    ui.end_row();
+
                     match &mut self
+                        .doc
                         .tree_table
                         .column_configs
                         .get_mut(self.edit_column_idx.unwrap())
@@ -789,31 +5338,243 @@ impl eframe::App for TreeTablesApp {
                     {
                         ColumnType::Number => (),
                         ColumnType::Text => (),
-                        ColumnType::MultiplyByFactor(input_col_id, factor) => {
+                        ColumnType::Duration => (),
+                        ColumnType::Boolean => (),
+                        ColumnType::Markup(input_col_id, steps) => {
+                            ui.label("Input Column:");
+                            ui.horizontal(|ui| {
+                                for col_cfg in column_configs.iter() {
+                                    if *current_column_id != col_cfg.id
+                                        && ui
+                                            .selectable_label(
+                                                col_cfg.id == *input_col_id,
+                                                col_cfg.caption.clone(),
+                                            )
+                                            .clicked()
+                                    {
+                                        *input_col_id = col_cfg.id.clone();
+                                    }
+                                }
+                            });
+                            ui.end_row();
+
+                            ui.label("Steps:");
+                            ui.vertical(|ui| {
+                                let mut delete_idx = None;
+                                for (idx, step) in steps.iter_mut().enumerate() {
+                                    ui.horizontal(|ui| {
+                                        ui.add(
+                                            egui::TextEdit::singleline(&mut step.name)
+                                                .desired_width(100.0),
+                                        );
+                                        ui.selectable_value(&mut step.percent, true, "%");
+                                        ui.selectable_value(&mut step.percent, false, "Amount");
+                                        factor_edit(
+                                            ui,
+                                            ("markup_step", idx),
+                                            &mut step.value,
+                                            &parameters,
+                                        );
+                                        if ui.button("🗑").clicked() {
+                                            delete_idx = Some(idx);
+                                        }
+                                    });
+                                }
+                                if let Some(idx) = delete_idx {
+                                    steps.remove(idx);
+                                }
+                                if ui.button("+").clicked() {
+                                    steps.push(MarkupStep {
+                                        name: format!("Step {}", steps.len() + 1),
+                                        ..MarkupStep::default()
+                                    });
+                                }
+                            });
+                            ui.end_row();
+
+                            if let Some(results) = preview_results.as_ref() {
+                                ui.label(format!("{preview_name}:"));
+                                ui.label(markup_breakdown(steps, results, &preview_unit, true));
+                                ui.end_row();
+                            }
+                        }
+                        ColumnType::ShareOfParent(input_col_id)
+                        | ColumnType::ShareOfTotal(input_col_id) => {
+                            ui.label("Input Column:");
+                            ui.horizontal(|ui| {
+                                for col_cfg in column_configs.iter() {
+                                    if *current_column_id != col_cfg.id
+                                        && ui
+                                            .selectable_label(
+                                                col_cfg.id == *input_col_id,
+                                                col_cfg.caption.clone(),
+                                            )
+                                            .clicked()
+                                    {
+                                        *input_col_id = col_cfg.id.clone();
+                                    }
+                                }
+                            });
+                            ui.end_row();
+                        }
+                        ColumnType::SumIf(input_col_id, condition) => {
                             ui.label("Input Column:");
                             ui.horizontal(|ui| {
                                 for col_cfg in column_configs.iter() {
-                                    if *current_column_id != col_cfg.id {
-                                        if ui
+                                    if *current_column_id != col_cfg.id
+                                        && ui
                                             .selectable_label(
                                                 col_cfg.id == *input_col_id,
                                                 col_cfg.caption.clone(),
                                             )
                                             .clicked()
+                                    {
+                                        *input_col_id = col_cfg.id.clone();
+                                    }
+                                }
+                            });
+                            ui.end_row();
+
+                            ui.label("Condition:");
+                            ui.horizontal(|ui| {
+                                let categorical: Vec<&ColumnConfig> = (column_configs.iter())
+                                    .filter(|cfg| cfg.col_type.is_categorical())
+                                    .collect();
+                                let selected =
+                                    categorical.iter().find(|cfg| cfg.id == condition.col_id);
+                                egui::ComboBox::from_id_source("condition_column")
+                                    .selected_text(selected.map_or("", |cfg| &cfg.caption))
+                                    .show_ui(ui, |ui| {
+                                        for cfg in categorical.iter() {
+                                            ui.selectable_value(
+                                                &mut condition.col_id,
+                                                cfg.id.clone(),
+                                                &cfg.caption,
+                                            );
+                                        }
+                                    });
+                                egui::ComboBox::from_id_source("condition_operator")
+                                    .width(30.0)
+                                    .selected_text(if condition.negated { "≠" } else { "=" })
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(&mut condition.negated, false, "=");
+                                        ui.selectable_value(&mut condition.negated, true, "≠");
+                                    });
+                                let values: Vec<(String, String)> = match selected
+                                    .map(|cfg| &cfg.col_type)
+                                {
+                                    Some(ColumnType::Enum(values)) => {
+                                        std::iter::once(("".to_owned(), "–".to_owned()))
+                                            .chain(values.iter().map(|v| (v.clone(), v.clone())))
+                                            .collect()
+                                    }
+                                    Some(_) => vec![
+                                        ("true".to_owned(), "Checked".to_owned()),
+                                        ("".to_owned(), "Unchecked".to_owned()),
+                                    ],
+                                    None => vec![],
+                                };
+                                let selected_text = (values.iter())
+                                    .find(|(value, _)| *value == condition.value)
+                                    .map_or(condition.value.clone(), |(_, text)| text.clone());
+                                egui::ComboBox::from_id_source("condition_value")
+                                    .selected_text(selected_text)
+                                    .show_ui(ui, |ui| {
+                                        for (value, text) in values {
+                                            ui.selectable_value(&mut condition.value, value, text);
+                                        }
+                                    });
+                            });
+                            ui.end_row();
+                        }
+                        ColumnType::Enum(values) => {
+                            ui.label("Values:");
+                            ui.vertical(|ui| {
+                                let new_value = format!("Value {}", values.len() + 1);
+                                edit_list(ui, values, new_value, &mut false);
+                            });
+                            ui.end_row();
+                        }
+                        ColumnType::Date(rollup) => {
+                            ui.label("Parent rows:");
+                            ui.horizontal(|ui| {
+                                ui.selectable_value(rollup, DateRollup::Earliest, "Earliest");
+                                ui.selectable_value(rollup, DateRollup::Latest, "Latest");
+                            });
+                            ui.end_row();
+                        }
+                        ColumnType::Gantt(start_col_id, end_col_id) => {
+                            let date_columns: Vec<&ColumnConfig> = (column_configs.iter())
+                                .filter(|cfg| matches!(cfg.col_type, ColumnType::Date(_)))
+                                .collect();
+                            for (label, col_id) in [("Start:", start_col_id), ("End:", end_col_id)]
+                            {
+                                ui.label(label);
+                                ui.horizontal(|ui| {
+                                    for col_cfg in date_columns.iter() {
+                                        if ui
+                                            .selectable_label(
+                                                col_cfg.id == *col_id,
+                                                col_cfg.caption.clone(),
+                                            )
+                                            .clicked()
                                         {
-                                            *input_col_id = col_cfg.id.clone();
+                                            *col_id = col_cfg.id.clone();
                                         }
                                     }
+                                });
+                                ui.end_row();
+                            }
+                        }
+                        ColumnType::Currency => {
+                            ui.label("");
+                            ui.label(
+                                "The unit is the currency of the sums, see View → Exchange rates.",
+                            );
+                            ui.end_row();
+                        }
+                        ColumnType::MultiplyByFactor(input_col_id, factor) => {
+                            ui.label("Input Column:");
+                            ui.horizontal(|ui| {
+                                for col_cfg in column_configs.iter() {
+                                    if *current_column_id != col_cfg.id
+                                        && ui
+                                            .selectable_label(
+                                                col_cfg.id == *input_col_id,
+                                                col_cfg.caption.clone(),
+                                            )
+                                            .clicked()
+                                    {
+                                        *input_col_id = col_cfg.id.clone();
+                                    }
                                 }
                             });
 
                             ui.end_row();
 
                             ui.label("Factor:");
-                            ui.add(egui::DragValue::new(factor));
+                            ui.horizontal(|ui| factor_edit(ui, "factor", factor, &parameters));
+                            ui.end_row();
+                        }
+                        ColumnType::RowSum(input_col_ids) => {
+                            ui.label("Input Columns:");
+                            ui.horizontal(|ui| {
+                                for col_cfg in column_configs.iter() {
+                                    if *current_column_id == col_cfg.id {
+                                        continue;
+                                    }
+                                    let mut checked = input_col_ids.contains(&col_cfg.id);
+                                    if ui.checkbox(&mut checked, col_cfg.caption.clone()).changed()
+                                    {
+                                        match checked {
+                                            true => input_col_ids.push(col_cfg.id.clone()),
+                                            false => input_col_ids.retain(|id| *id != col_cfg.id),
+                                        }
+                                    }
+                                }
+                            });
                             ui.end_row();
                         }
-                        ColumnType::RowSum(_ref_col_ids) => (),
                     }
                 });
 
@@ -826,16 +5587,1189 @@ impl eframe::App for TreeTablesApp {
                         .button(RichText::new("🗑").color(egui::Color32::RED))
                         .clicked()
                     {
-                        self.tree_table
+                        self.doc
+                            .tree_table
                             .column_configs
                             .remove(self.edit_column_idx.unwrap());
 
-                        // dbg!(&self.tree_table.column_configs);
+                        // dbg!(&self.doc.tree_table.column_configs);
 
                         self.edit_column_idx = None;
                     }
                 });
+
+                if self.doc.tree_table.column_configs != column_configs {
+                    self.doc.mark_all_modified();
+                }
             });
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NUMBER: &str = "number";
+    const FACTOR: &str = "factor";
+    const SUM: &str = "sum";
+
+    fn column(id: &str, col_type: ColumnType) -> ColumnConfig {
+        ColumnConfig {
+            id: id.to_owned(),
+            col_type,
+            ..ColumnConfig::default()
+        }
+    }
+
+    fn leaf(number: f64, multiplier: f64) -> RowData {
+        RowData {
+            col_data: BTreeMap::from([(NUMBER.to_owned(), number)]),
+            multiplier,
+            ..RowData::default()
+        }
+    }
+
+    fn parent(children: Vec<RowData>, multiplier: f64) -> RowData {
+        RowData {
+            children,
+            multiplier,
+            ..RowData::default()
+        }
+    }
+
+    // The sum column comes before its input, the factor column
+    fn table(root_row: RowData) -> TreeTable {
+        TreeTable {
+            title_text: "".to_owned(),
+            column_configs: vec![
+                column(NUMBER, ColumnType::Number),
+                column(
+                    SUM,
+                    ColumnType::RowSum(vec![NUMBER.to_owned(), FACTOR.to_owned()]),
+                ),
+                column(
+                    FACTOR,
+                    ColumnType::MultiplyByFactor(NUMBER.to_owned(), Factor::Value(10.0)),
+                ),
+            ],
+            root_row,
+            conflicts: vec![],
+            scenarios: vec![],
+            active_scenario: None,
+            base_values: BTreeMap::new(),
+            parameters: vec![],
+            exchange_rates: vec![],
+        }
+    }
+
+    fn values(row: &RowData) -> [f64; 3] {
+        [NUMBER, FACTOR, SUM].map(|col_id| *row.col_data.get(col_id).unwrap_or(&0.0))
+    }
+
+    #[test]
+    fn leaf_values_are_per_unit() {
+        let mut table = table(parent(vec![leaf(2.0, 3.0)], 1.0));
+        table.update();
+
+        assert_eq!(values(&table.root_row.children[0]), [2.0, 20.0, 22.0]);
+        assert_eq!(values(&table.root_row), [6.0, 60.0, 66.0]);
+    }
+
+    #[test]
+    fn multiplier_scales_subtree_for_every_column_type() {
+        // root
+        // └─ a (2x)
+        //    ├─ b (3x)
+        //    │  ├─ 1 (1x)
+        //    │  └─ 2 (4x)
+        //    └─ 5 (1x)
+        let b = parent(vec![leaf(1.0, 1.0), leaf(2.0, 4.0)], 3.0);
+        let a = parent(vec![b, leaf(5.0, 1.0)], 2.0);
+        let mut table = table(parent(vec![a], 1.0));
+        table.update();
+
+        let a = &table.root_row.children[0];
+        assert_eq!(values(&a.children[0]), [9.0, 90.0, 99.0]);
+        assert_eq!(values(a), [32.0, 320.0, 352.0]);
+        assert_eq!(values(&table.root_row), [64.0, 640.0, 704.0]);
+    }
+
+    #[test]
+    fn disabled_rows_are_left_out() {
+        let mut disabled = parent(vec![leaf(1.0, 1.0)], 2.0);
+        disabled.enabled = false;
+        let a = parent(vec![disabled, leaf(5.0, 1.0)], 2.0);
+        let mut table = table(parent(vec![a], 1.0));
+        table.update();
+
+        let a = &table.root_row.children[0];
+        assert_eq!(values(&a.children[0]), [1.0, 10.0, 11.0]);
+        assert_eq!(values(&table.root_row), [10.0, 100.0, 110.0]);
+    }
+
+    #[test]
+    fn pinned_values_are_scaled_by_multiplier() {
+        let mut b = parent(vec![leaf(1.0, 1.0), leaf(2.0, 1.0)], 3.0);
+        b.pinned.insert(FACTOR.to_owned(), 50.0);
+        let mut table = table(parent(vec![b], 1.0));
+        table.update();
+
+        // The sum of b uses the pinned value
+        let b = &table.root_row.children[0];
+        assert_eq!(values(b), [3.0, 50.0, 53.0]);
+        assert_eq!(b.calculated.get(FACTOR), Some(&30.0));
+        assert_eq!(values(&table.root_row), [9.0, 150.0, 159.0]);
+    }
+
+    #[test]
+    fn pinned_inputs_of_parents_feed_their_derived_columns() {
+        let mut matching = leaf(1.0, 1.0);
+        matching
+            .categories
+            .insert("status".to_owned(), "done".to_owned());
+        let mut b = parent(vec![matching, leaf(3.0, 1.0)], 2.0);
+        b.pinned.insert(NUMBER.to_owned(), 8.0);
+        let mut table = table(parent(vec![b], 1.0));
+        let condition = Condition {
+            col_id: "status".to_owned(),
+            negated: false,
+            value: "done".to_owned(),
+        };
+        let overhead = MarkupStep {
+            name: "Overhead".to_owned(),
+            percent: true,
+            value: Factor::Value(50.0),
+        };
+        let shipping = MarkupStep {
+            name: "Shipping".to_owned(),
+            percent: false,
+            value: Factor::Value(1.0),
+        };
+        table.column_configs.extend([
+            column("status", ColumnType::Enum(vec!["done".to_owned()])),
+            column("done", ColumnType::SumIf(NUMBER.to_owned(), condition)),
+            column(
+                "markup",
+                ColumnType::Markup(NUMBER.to_owned(), vec![overhead, shipping]),
+            ),
+        ]);
+        table.update();
+
+        let b = &table.root_row.children[0];
+        assert_eq!(values(b), [8.0, 80.0, 88.0]);
+        // The pinned value doubles the input, so the matching part doubles as well
+        assert_eq!(b.col_data["done"], 2.0);
+        // (1 * 1.5 + 1) + (3 * 1.5 + 1) + (8 - 4) * 1.5
+        assert_eq!(b.col_data["markup"], 14.0);
+        assert_eq!(b.markup_results["markup"], [8.0, 12.0, 14.0]);
+        assert_eq!(values(&table.root_row), [16.0, 160.0, 176.0]);
+        assert_eq!(table.root_row.col_data["done"], 4.0);
+        assert_eq!(table.root_row.col_data["markup"], 28.0);
+    }
+
+    #[test]
+    fn changed_multiplier_recalculates_ancestors() {
+        let b = parent(vec![leaf(1.0, 1.0)], 1.0);
+        let mut table = table(parent(vec![parent(vec![b], 1.0)], 1.0));
+        table.update();
+
+        table.root_row.get_row_mut(&[0, 0]).unwrap().multiplier = 4.0;
+        table.root_row.mark_dirty(&[0, 0]);
+        table.update();
+
+        assert_eq!(values(&table.root_row), [4.0, 40.0, 44.0]);
+    }
+
+    #[test]
+    fn quantities_are_parsed_with_german_numbers() {
+        assert_eq!(parse_quantity("90 min"), Some((90.0, Some("min"))));
+        assert_eq!(parse_quantity("1,5kg"), Some((1.5, Some("kg"))));
+        assert_eq!(parse_quantity("1.500,5 €"), Some((1500.5, Some("€"))));
+        assert_eq!(parse_quantity("-2"), Some((-2.0, None)));
+        // An English decimal point isn't taken as a thousands separator
+        assert_eq!(parse_quantity("1.5 kg"), None);
+        assert_eq!(parse_quantity("1.50"), None);
+        assert_eq!(parse_quantity("h"), None);
+    }
+
+    #[test]
+    fn quantities_are_converted_to_column_unit() {
+        let mut in_minutes = leaf(0.0, 2.0);
+        let quantity = Quantity {
+            amount: 90.0,
+            unit: "min".to_owned(),
+        };
+        in_minutes.quantities.insert(NUMBER.to_owned(), quantity);
+        let mut table = table(parent(vec![in_minutes, leaf(1.0, 1.0)], 1.0));
+        table.column_configs[0].unit = "h".to_owned();
+        table.update();
+
+        assert_eq!(values(&table.root_row.children[0])[0], 1.5);
+        assert_eq!(values(&table.root_row)[0], 4.0);
+    }
+
+    #[test]
+    fn sums_flag_incompatible_units() {
+        let mut table = table(RowData::default());
+        table.column_configs[0].unit = "h".to_owned();
+        table.column_configs[1].unit = "min".to_owned();
+        table.column_configs[2].unit = "€".to_owned();
+
+        assert!(table.unit_mismatch(0).is_none());
+        let mismatch = table.unit_mismatch(1).unwrap();
+        assert!(mismatch.contains("€") && !mismatch.contains("h)"));
+    }
+
+    #[test]
+    fn amounts_without_exchange_rate_are_left_out() {
+        let mut in_francs = leaf(0.0, 2.0);
+        let quantity = Quantity {
+            amount: 100.0,
+            unit: "CHF".to_owned(),
+        };
+        in_francs.quantities.insert(NUMBER.to_owned(), quantity);
+        let mut disabled = in_francs.clone();
+        disabled.enabled = false;
+        let mut table = table(parent(
+            vec![parent(vec![in_francs], 1.0), leaf(1.0, 1.0)],
+            1.0,
+        ));
+        table.root_row.children.push(disabled);
+        table.column_configs[0].col_type = ColumnType::Currency;
+        table.column_configs[0].unit = "EUR".to_owned();
+        table.update();
+
+        assert_eq!(values(&table.root_row)[0], 1.0);
+        let mismatch = table.unit_mismatch(0).unwrap();
+        assert!(mismatch.contains("CHF"));
+
+        table.exchange_rates = parse_exchange_rates("EUR;1\nCHF;1,1\n");
+        table.root_row.mark_all_dirty();
+        table.update();
+        assert!(table.unit_mismatch(0).is_none());
+    }
+
+    #[test]
+    fn currencies_are_summed_in_reporting_currency() {
+        let mut in_dollars = leaf(0.0, 2.0);
+        let quantity = Quantity {
+            amount: 100.0,
+            unit: "USD".to_owned(),
+        };
+        in_dollars.quantities.insert(NUMBER.to_owned(), quantity);
+        let mut table = table(parent(vec![in_dollars, leaf(10.0, 1.0)], 1.0));
+        table.column_configs[0].col_type = ColumnType::Currency;
+        table.column_configs[0].unit = "CHF".to_owned();
+        table.exchange_rates = parse_exchange_rates("Currency;Rate\nEUR;1\nUSD;0,9\nCHF;1,2\n");
+        table.update();
+
+        assert_eq!(values(&table.root_row.children[0])[0], 75.0);
+        assert_eq!(values(&table.root_row)[0], 160.0);
+    }
+
+    #[test]
+    fn dates_roll_up_to_earliest_and_latest() {
+        let date = |text: &str| parse_date(text).unwrap();
+        let dated = |start: &str, end: &str| RowData {
+            col_data: BTreeMap::from([
+                (NUMBER.to_owned(), date(start)),
+                (FACTOR.to_owned(), date(end)),
+            ]),
+            ..RowData::default()
+        };
+        let mut disabled = dated("2023-12-01", "2024-12-31");
+        disabled.enabled = false;
+        let children = vec![
+            dated("2024-03-01", "2024-03-15"),
+            dated("2024-02-28", "2024-03-10"),
+            disabled,
+            RowData::default(),
+        ];
+        let mut table = table(parent(vec![parent(children, 1.0)], 1.0));
+        table.column_configs = vec![
+            column(NUMBER, ColumnType::Date(DateRollup::Earliest)),
+            column(FACTOR, ColumnType::Date(DateRollup::Latest)),
+        ];
+        table.update();
+
+        let [start, end, _] = values(&table.root_row);
+        assert_eq!(format_date(start), "2024-02-28");
+        assert_eq!(format_date(end), "2024-03-15");
+        assert_eq!(parse_date("15.03.2024"), Some(end));
+        assert_eq!(parse_date("2023-02-29"), None);
+    }
+
+    #[test]
+    fn pinned_dates_of_parents_roll_up() {
+        let date = |text: &str| parse_date(text).unwrap();
+        let dated = |text: &str| RowData {
+            col_data: BTreeMap::from([(NUMBER.to_owned(), date(text))]),
+            ..RowData::default()
+        };
+        let mut delayed = parent(vec![dated("2024-03-01")], 1.0);
+        delayed.pinned.insert(NUMBER.to_owned(), date("2024-04-01"));
+        let mut table = table(parent(vec![delayed, dated("2024-03-15")], 1.0));
+        table.column_configs = vec![column(NUMBER, ColumnType::Date(DateRollup::Earliest))];
+        table.update();
+
+        assert_eq!(values(&table.root_row.children[0])[0], date("2024-04-01"));
+        assert_eq!(values(&table.root_row)[0], date("2024-03-15"));
+    }
+
+    #[test]
+    fn columns_are_calculated_after_their_inputs() {
+        let table = table(RowData::default());
+        let order: Vec<&str> = calculation_order(&table.column_configs)
+            .iter()
+            .map(|cfg| cfg.id.as_str())
+            .collect();
+        assert_eq!(order, [NUMBER, FACTOR, SUM]);
+    }
+
+    #[test]
+    fn categories_are_counted_and_filtered() {
+        let status = |value: Option<&str>| RowData {
+            categories: value.map_or(BTreeMap::new(), |value| {
+                BTreeMap::from([(FACTOR.to_owned(), value.to_owned())])
+            }),
+            ..RowData::default()
+        };
+        let mut checked = status(None);
+        checked
+            .categories
+            .insert(NUMBER.to_owned(), "true".to_owned());
+        let mut disabled = status(Some("ordered"));
+        disabled.enabled = false;
+        let children = vec![checked, status(Some("planned")), status(None), disabled];
+        let mut table = table(parent(vec![parent(children, 1.0)], 1.0));
+        table.column_configs = vec![
+            column(NUMBER, ColumnType::Boolean),
+            column(
+                FACTOR,
+                ColumnType::Enum(vec!["planned".to_owned(), "ordered".to_owned()]),
+            ),
+        ];
+        table.update();
+
+        let root = &table.root_row;
+        assert_eq!(values(root), [1.0, 0.0, 0.0]);
+        assert_eq!(
+            root.counts.get(FACTOR),
+            Some(&BTreeMap::from([
+                ("".to_owned(), 2),
+                ("planned".to_owned(), 1)
+            ]))
+        );
+
+        let filter = RowFilter::Category {
+            col_id: FACTOR.to_owned(),
+            value: Some("ordered".to_owned()),
+        };
+        assert_eq!(
+            root.visible_paths(Some(&filter)),
+            [vec![], vec![0], vec![0, 3]]
+        );
+        let filter = RowFilter::Category {
+            col_id: NUMBER.to_owned(),
+            value: None,
+        };
+        assert_eq!(root.all_paths().len(), 6);
+        assert_eq!(root.visible_paths(Some(&filter)).len(), 5);
+    }
+
+    #[test]
+    fn conditional_sums_include_matching_leaves() {
+        let supplied = |number: f64, multiplier: f64, supplier: &str| {
+            let mut row = leaf(number, multiplier);
+            row.categories
+                .insert(FACTOR.to_owned(), supplier.to_owned());
+            row
+        };
+        let children = vec![
+            supplied(1.0, 2.0, "A"),
+            supplied(3.0, 1.0, "B"),
+            leaf(5.0, 1.0),
+        ];
+        let mut table = table(parent(vec![parent(children, 2.0)], 1.0));
+        let sum_if = |value: &str, negated| {
+            let condition = Condition {
+                col_id: FACTOR.to_owned(),
+                negated,
+                value: value.to_owned(),
+            };
+            ColumnType::SumIf(NUMBER.to_owned(), condition)
+        };
+        table.column_configs = vec![
+            column(NUMBER, ColumnType::Number),
+            column(
+                FACTOR,
+                ColumnType::Enum(vec!["A".to_owned(), "B".to_owned()]),
+            ),
+            column(SUM, sum_if("A", false)),
+            column("not_a", sum_if("A", true)),
+        ];
+        table.update();
+
+        let root = &table.root_row;
+        assert_eq!(values(root), [20.0, 0.0, 4.0]);
+        assert_eq!(root.col_data.get("not_a"), Some(&16.0));
+    }
+
+    #[test]
+    fn conditions_on_unknown_columns_match_empty_values() {
+        let mut table = table(parent(vec![leaf(1.0, 2.0), leaf(3.0, 1.0)], 1.0));
+        let sum_if = |value: &str, negated| {
+            let condition = Condition {
+                col_id: "removed".to_owned(),
+                negated,
+                value: value.to_owned(),
+            };
+            ColumnType::SumIf(NUMBER.to_owned(), condition)
+        };
+        table.column_configs = vec![
+            column(NUMBER, ColumnType::Number),
+            column(FACTOR, sum_if("A", false)),
+            column(SUM, sum_if("A", true)),
+            column("empty", sum_if("", false)),
+        ];
+        table.update();
+
+        // No row has a value in the missing column
+        assert_eq!(values(&table.root_row), [5.0, 0.0, 5.0]);
+        assert_eq!(table.root_row.col_data.get("empty"), Some(&5.0));
+    }
+
+    #[test]
+    fn shares_account_for_multipliers_and_disabled_rows() {
+        let mut disabled = leaf(5.0, 1.0);
+        disabled.enabled = false;
+        let a = parent(vec![leaf(1.0, 1.0), leaf(3.0, 1.0)], 2.0);
+        let mut table = table(parent(vec![a, leaf(2.0, 1.0), disabled], 1.0));
+        table.column_configs = vec![
+            column(NUMBER, ColumnType::Number),
+            column(FACTOR, ColumnType::ShareOfParent(NUMBER.to_owned())),
+            column(SUM, ColumnType::ShareOfTotal(NUMBER.to_owned())),
+        ];
+        table.update();
+
+        let root = &table.root_row;
+        let a = &root.children[0];
+        assert_eq!(values(root), [10.0, 100.0, 100.0]);
+        assert_eq!(values(a), [4.0, 80.0, 80.0]);
+        assert_eq!(values(&a.children[1]), [3.0, 75.0, 60.0]);
+        assert_eq!(values(&root.children[1]), [2.0, 20.0, 20.0]);
+        assert_eq!(values(&root.children[2]), [5.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn shares_follow_changed_rows() {
+        let a = parent(vec![leaf(1.0, 1.0), leaf(3.0, 1.0)], 2.0);
+        let mut table = table(parent(vec![a, leaf(2.0, 1.0)], 1.0));
+        table.column_configs = vec![
+            column(NUMBER, ColumnType::Number),
+            column(FACTOR, ColumnType::ShareOfParent(NUMBER.to_owned())),
+            column(SUM, ColumnType::ShareOfTotal(NUMBER.to_owned())),
+        ];
+        table.update();
+
+        // Swapping the values keeps the totals
+        let set_number = |table: &mut TreeTable, path: &[usize], number: f64| {
+            let mut row = &mut table.root_row;
+            for idx in path {
+                row = &mut row.children[*idx];
+            }
+            row.col_data.insert(NUMBER.to_owned(), number);
+            table.root_row.mark_dirty(path);
+        };
+        set_number(&mut table, &[0, 0], 3.0);
+        set_number(&mut table, &[0, 1], 1.0);
+        table.update();
+        let root = &table.root_row;
+        assert_eq!(values(&root.children[0].children[0]), [3.0, 75.0, 60.0]);
+        assert_eq!(values(&root.children[0].children[1]), [1.0, 25.0, 20.0]);
+        assert_eq!(values(&root.children[1]), [2.0, 20.0, 20.0]);
+
+        // A changed total changes the shares of the unchanged rows as well
+        set_number(&mut table, &[1], 12.0);
+        table.update();
+        let root = &table.root_row;
+        assert_eq!(values(&root.children[0]), [4.0, 40.0, 40.0]);
+        assert_eq!(values(&root.children[0].children[0]), [3.0, 75.0, 30.0]);
+        assert_eq!(values(&root.children[1]), [12.0, 60.0, 60.0]);
+
+        // So does a multiplier on the path
+        table.root_row.children[0].multiplier = 1.0;
+        table.root_row.mark_dirty(&[0]);
+        table.update();
+        let root = &table.root_row;
+        assert_eq!(values(&root.children[0].children[0]), [3.0, 75.0, 18.75]);
+    }
+
+    #[test]
+    fn incremental_updates_match_a_full_recalculation() {
+        let a = parent(vec![leaf(1.0, 1.0), leaf(3.0, 2.0)], 2.0);
+        let b = parent(vec![leaf(5.0, 1.0), parent(vec![leaf(7.0, 1.0)], 3.0)], 1.0);
+        let mut table = table(parent(vec![a, b, leaf(2.0, 1.0)], 1.0));
+        let condition = Condition {
+            col_id: "done".to_owned(),
+            negated: false,
+            value: "true".to_owned(),
+        };
+        table.column_configs.extend([
+            column("done", ColumnType::Boolean),
+            column("sum_if", ColumnType::SumIf(SUM.to_owned(), condition)),
+            column("share", ColumnType::ShareOfTotal(FACTOR.to_owned())),
+        ]);
+        table.update();
+
+        let mut edit = |path: &[usize], edit: &dyn Fn(&mut RowData)| {
+            edit(table.root_row.get_row_mut(path).unwrap());
+            table.root_row.mark_dirty(path);
+            table.update();
+
+            let mut recalculated = table.clone();
+            recalculated.root_row.mark_all_dirty();
+            recalculated.update();
+            for path in table.root_row.all_paths() {
+                let row = table.root_row.get_row(&path).unwrap();
+                let expected = recalculated.root_row.get_row(&path).unwrap();
+                assert_eq!(row.col_data, expected.col_data, "row {path:?}");
+            }
+        };
+        edit(&[0, 1], &|row| {
+            row.col_data.insert(NUMBER.to_owned(), 4.0);
+        });
+        edit(&[1, 1, 0], &|row| {
+            row.categories.insert("done".to_owned(), "true".to_owned());
+        });
+        edit(&[1, 1], &|row| row.multiplier = 0.5);
+        edit(&[0], &|row| row.enabled = false);
+        edit(&[1, 0], &|row| {
+            row.pinned.insert(SUM.to_owned(), 100.0);
+        });
+    }
+
+    #[test]
+    fn markup_steps_are_applied_in_order() {
+        let steps = vec![
+            MarkupStep {
+                name: "Overhead".to_owned(),
+                percent: true,
+                value: Factor::Value(25.0),
+            },
+            MarkupStep {
+                name: "Shipping".to_owned(),
+                percent: false,
+                value: Factor::Parameter("shipping".to_owned()),
+            },
+            MarkupStep {
+                name: "VAT".to_owned(),
+                percent: true,
+                value: Factor::Parameter("vat".to_owned()),
+            },
+        ];
+        let mut table = table(parent(vec![leaf(100.0, 2.0)], 1.0));
+        table.column_configs = vec![
+            column(NUMBER, ColumnType::Number),
+            column(SUM, ColumnType::Markup(NUMBER.to_owned(), steps)),
+        ];
+        table.parameters = vec![
+            Parameter {
+                name: "shipping".to_owned(),
+                value: 15.0,
+            },
+            Parameter {
+                name: "vat".to_owned(),
+                value: 50.0,
+            },
+        ];
+        table.update();
+
+        let root = &table.root_row;
+        assert_eq!(
+            root.children[0].markup_results[SUM],
+            [100.0, 125.0, 140.0, 210.0]
+        );
+        assert_eq!(root.markup_results[SUM], [200.0, 250.0, 280.0, 420.0]);
+        assert_eq!(values(root)[2], 420.0);
+    }
+
+    #[test]
+    fn markups_without_steps_pass_the_input_through() {
+        let mut a = parent(vec![leaf(1.0, 1.0), leaf(3.0, 1.0)], 2.0);
+        a.pinned.insert(NUMBER.to_owned(), 5.0);
+        let mut table = table(parent(vec![a, leaf(2.0, 3.0)], 1.0));
+        table.column_configs = vec![
+            column(NUMBER, ColumnType::Number),
+            column(SUM, ColumnType::Markup(NUMBER.to_owned(), vec![])),
+        ];
+        table.update();
+
+        let root = &table.root_row;
+        assert_eq!(root.children[0].children[0].markup_results[SUM], [1.0]);
+        assert_eq!(root.children[0].markup_results[SUM], [5.0]);
+        assert_eq!(values(root), [16.0, 0.0, 16.0]);
+    }
+
+    #[test]
+    fn tags_filter_rows_with_their_subtrees() {
+        let mut tagged = parent(vec![leaf(1.0, 1.0), leaf(2.0, 1.0)], 1.0);
+        tagged.tags = vec!["steel".to_owned()];
+        let mut tagged_leaf = leaf(3.0, 1.0);
+        tagged_leaf.tags = vec!["steel".to_owned(), "quote".to_owned()];
+        let other = parent(vec![tagged_leaf, leaf(4.0, 1.0)], 1.0);
+        let root = parent(vec![tagged, other, leaf(5.0, 1.0)], 1.0);
+
+        let tags: Vec<String> = root.all_tags().into_iter().collect();
+        assert_eq!(tags, ["quote", "steel"]);
+        let filter = RowFilter::Tag("steel".to_owned());
+        let paths = [vec![], vec![0], vec![0, 0], vec![0, 1], vec![1], vec![1, 0]];
+        assert_eq!(root.visible_paths(Some(&filter)), paths);
+    }
+
+    #[test]
+    fn notes_are_exported_on_request() {
+        let mut row = leaf(1.0, 1.0);
+        row.name = "Frame".to_owned();
+        row.notes = "Quote from\nMay".to_owned();
+        row.tags = vec!["steel".to_owned(), "quote".to_owned()];
+        let table = table(parent(vec![row], 1.0));
+
+        assert!(!table.to_csv(false).contains("Notes"));
+        let csv = table.to_csv(true);
+        assert!(csv.lines().next().unwrap().ends_with(";Notes;Tags;Links"));
+        assert!(csv.contains(";\"Quote from\nMay\";steel, quote;\n"));
+    }
+
+    #[test]
+    fn exported_notes_are_quoted() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a;b"), "\"a;b\"");
+        assert_eq!(csv_field("12\" pipe"), "\"12\"\" pipe\"");
+
+        let mut row = leaf(1.0, 1.0);
+        row.notes = "Length 12\"; see \"Quote\"".to_owned();
+        row.links = vec!["https://example.com/a;b".to_owned()];
+        let csv = table(parent(vec![row], 1.0)).to_csv(true);
+        let line = csv.lines().nth(2).unwrap();
+        assert!(
+            line.ends_with(";\"Length 12\"\"; see \"\"Quote\"\"\";;\"https://example.com/a;b\"")
+        );
+    }
+
+    #[test]
+    fn linked_templates_follow_the_template() {
+        let mut component = parent(vec![leaf(2.0, 1.0), leaf(3.0, 2.0)], 4.0);
+        component.name = "Component".to_owned();
+        let editable = [&NUMBER.to_owned()];
+        let mut library = Library {
+            templates: vec![Template {
+                id: "component".to_owned(),
+                row: component.to_template(&editable),
+            }],
+        };
+        let template = &library.templates[0];
+        assert_eq!(template.row.multiplier, 1.0);
+
+        let linked = RowData::from_template(template, true);
+        let copied = RowData::from_template(template, false);
+        let child_ids = |row: &RowData| -> Vec<String> {
+            row.children.iter().map(|child| child.id.clone()).collect()
+        };
+        assert_ne!(child_ids(&linked), child_ids(&copied));
+        assert!(child_ids(&linked)[0].starts_with(&linked.id));
+        let mut table = table(parent(vec![linked, copied], 1.0));
+        table.root_row.children[0].multiplier = 2.0;
+        table.update();
+        assert_eq!(values(&table.root_row)[0], 24.0);
+        assert!(table.outdated_templates(&library).is_empty());
+        assert!(!table.apply_templates(&library));
+
+        library.templates[0].row.children[0]
+            .col_data
+            .insert(NUMBER.to_owned(), 5.0);
+        let ids = child_ids(&table.root_row.children[0]);
+        let linked_id = table.root_row.children[0].id.clone();
+        let outdated = table.outdated_templates(&library);
+        assert_eq!(outdated, [(linked_id, "Component".to_owned())]);
+        assert!(table.apply_templates(&library));
+        table.update();
+        assert_eq!(child_ids(&table.root_row.children[0]), ids);
+        assert_eq!(values(&table.root_row.children[0])[0], 11.0);
+        assert_eq!(values(&table.root_row)[0], 30.0);
+    }
+
+    #[test]
+    fn scenarios_keep_their_quantities() {
+        let mut table = table(parent(vec![leaf(2.0, 1.0)], 1.0));
+        let metres = |amount| Quantity {
+            amount,
+            unit: "m".to_owned(),
+        };
+        table.root_row.children[0]
+            .quantities
+            .insert(NUMBER.to_owned(), metres(2.0));
+        table.scenarios.push(Scenario::default());
+
+        table.set_scenario(Some(0));
+        table.root_row.children[0]
+            .quantities
+            .insert(NUMBER.to_owned(), metres(3.0));
+        table.set_scenario(None);
+        let quantity =
+            |table: &TreeTable| table.root_row.children[0].quantities.get(NUMBER).cloned();
+        assert_eq!(quantity(&table), Some(metres(2.0)));
+
+        table.set_scenario(Some(0));
+        assert_eq!(quantity(&table), Some(metres(3.0)));
+        table.root_row.children[0].quantities.clear();
+        table.set_scenario(None);
+        assert_eq!(quantity(&table), Some(metres(2.0)));
+        table.set_scenario(Some(0));
+        assert_eq!(quantity(&table), None);
+    }
+
+    #[test]
+    fn scenarios_keep_their_categories() {
+        let mut table = table(parent(vec![leaf(2.0, 1.0)], 1.0));
+        table.scenarios.push(Scenario::default());
+        let category =
+            |table: &TreeTable| table.root_row.children[0].categories.get(NUMBER).cloned();
+
+        table.set_scenario(Some(0));
+        table.root_row.children[0]
+            .categories
+            .insert(NUMBER.to_owned(), "true".to_owned());
+        table.set_scenario(None);
+        assert_eq!(category(&table), None);
+        table.set_scenario(Some(0));
+        assert_eq!(category(&table).as_deref(), Some("true"));
+    }
+
+    fn document() -> Document {
+        let a = parent(vec![leaf(1.0, 1.0), leaf(2.0, 1.0)], 1.0);
+        let b = parent(vec![leaf(3.0, 1.0)], 1.0);
+        Document::new(table(parent(vec![a, b], 1.0)), "undo_test.tt")
+    }
+
+    // Changes the first leaf of a and finishes the change
+    fn edit(doc: &mut Document, number: f64) {
+        let leaf = &mut doc.tree_table.root_row.children[0].children[0];
+        leaf.col_data.insert(NUMBER.to_owned(), number);
+        doc.mark_changed();
+        doc.record_undo_step();
+    }
+
+    fn first_leaf(doc: &Document) -> f64 {
+        values(&doc.tree_table.root_row.children[0].children[0])[0]
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_table() {
+        let mut doc = document();
+        edit(&mut doc, 10.0);
+        edit(&mut doc, 20.0);
+
+        doc.undo();
+        assert_eq!(first_leaf(&doc), 10.0);
+        doc.undo();
+        assert_eq!(first_leaf(&doc), 1.0);
+        doc.undo();
+        assert_eq!(first_leaf(&doc), 1.0);
+
+        doc.redo();
+        assert_eq!(first_leaf(&doc), 10.0);
+        doc.redo();
+        assert_eq!(first_leaf(&doc), 20.0);
+        assert!(doc.redo_stack.is_empty());
+    }
+
+    #[test]
+    fn unfinished_changes_are_undone_first() {
+        let mut doc = document();
+        edit(&mut doc, 10.0);
+        doc.tree_table.root_row.children[1].name = "b".to_owned();
+        doc.mark_changed();
+
+        doc.undo();
+        assert_eq!(doc.tree_table.root_row.children[1].name, "");
+        assert_eq!(first_leaf(&doc), 10.0);
+        doc.redo();
+        assert_eq!(doc.tree_table.root_row.children[1].name, "b");
+    }
+
+    #[test]
+    fn a_new_change_clears_the_redo_steps() {
+        let mut doc = document();
+        edit(&mut doc, 10.0);
+        edit(&mut doc, 20.0);
+        doc.undo();
+        assert_eq!(doc.redo_stack.len(), 1);
+
+        edit(&mut doc, 30.0);
+        assert!(doc.redo_stack.is_empty());
+        doc.redo();
+        assert_eq!(first_leaf(&doc), 30.0);
+        doc.undo();
+        assert_eq!(first_leaf(&doc), 10.0);
+    }
+
+    #[test]
+    fn undo_steps_share_unchanged_rows() {
+        let mut doc = document();
+        edit(&mut doc, 10.0);
+
+        let before = &doc.undo_stack[0].root;
+        let after = &doc.undo_base.root;
+        assert!(!Rc::ptr_eq(before, after));
+        // Only the changed leaf and its ancestors are stored again
+        assert!(!Rc::ptr_eq(&before.children[0], &after.children[0]));
+        assert!(Rc::ptr_eq(
+            &before.children[0].children[1],
+            &after.children[0].children[1]
+        ));
+        assert!(Rc::ptr_eq(&before.children[1], &after.children[1]));
+    }
+
+    #[test]
+    fn selection_roots_drop_the_root_and_selected_descendants() {
+        let selected = [
+            vec![1, 0],
+            vec![],
+            vec![0],
+            vec![0, 2],
+            vec![1, 0],
+            vec![1, 0, 3],
+        ];
+        assert_eq!(selection_roots(&selected), [vec![0], vec![1, 0]]);
+        assert!(selection_roots(&[vec![]]).is_empty());
+    }
+
+    #[test]
+    fn collapsed_rows_hide_their_subtree() {
+        let mut a = parent(vec![leaf(1.0, 1.0), leaf(2.0, 1.0)], 1.0);
+        a.expanded = false;
+        let b = parent(vec![leaf(3.0, 1.0)], 1.0);
+        let root = parent(vec![a, b], 1.0);
+
+        let visible = [vec![], vec![0], vec![1], vec![1, 0]];
+        assert_eq!(root.visible_paths(None), visible);
+        assert_eq!(root.all_paths().len(), 6);
+    }
+
+    #[test]
+    fn cursor_moves_over_visible_rows_and_columns() {
+        let mut a = parent(vec![leaf(1.0, 1.0)], 1.0);
+        a.expanded = false;
+        let mut app = TreeTablesApp {
+            doc: Document::new(
+                table(parent(vec![a, leaf(2.0, 1.0)], 1.0)),
+                "cursor_test.tt",
+            ),
+            ..TreeTablesApp::default()
+        };
+        app.doc.tree_table.column_configs[1].hidden = true;
+        let mut cursor = |row_path: Vec<usize>, col_idx, row_delta, col_delta| {
+            app.doc.nav.cursor = Some(CellCursor { row_path, col_idx });
+            app.move_cursor(row_delta, col_delta);
+            let cursor = app.doc.nav.cursor.as_ref().unwrap();
+            (cursor.row_path.clone(), cursor.col_idx)
+        };
+
+        // The collapsed child of a and the hidden sum column are skipped
+        assert_eq!(cursor(vec![0], 1, 1, 1), (vec![1], 3));
+        assert_eq!(cursor(vec![1], 3, -1, -1), (vec![0], 1));
+        // The cursor stops at the edges
+        assert_eq!(cursor(vec![1], 3, 5, 5), (vec![1], 3));
+        assert_eq!(cursor(vec![], 0, -1, -1), (vec![], 0));
+    }
+
+    #[test]
+    fn bulk_values_are_only_set_on_amount_columns() {
+        let mut app = TreeTablesApp {
+            doc: Document::new(
+                table(parent(vec![leaf(1.0, 1.0), leaf(2.0, 1.0)], 1.0)),
+                "bulk_test.tt",
+            ),
+            ..TreeTablesApp::default()
+        };
+        let tree_table = &mut app.doc.tree_table;
+        tree_table.column_configs.extend([
+            column("date", ColumnType::Date(DateRollup::Earliest)),
+            column("done", ColumnType::Boolean),
+        ]);
+        tree_table.root_row.children[1]
+            .quantities
+            .insert(NUMBER.to_owned(), Quantity::default());
+        app.doc.nav.selected_rows = vec![vec![], vec![0], vec![1]];
+
+        app.set_selected_value(0, 5.0);
+        app.set_selected_value(3, 5.0);
+        app.set_selected_value(4, 5.0);
+        app.doc.tree_table.update();
+
+        let root = &app.doc.tree_table.root_row;
+        assert_eq!(root.children[0].col_data[NUMBER], 5.0);
+        assert!(root.children[1].quantities.is_empty());
+        assert_eq!(root.col_data[NUMBER], 10.0);
+        assert!(!root.children[0].col_data.contains_key("date"));
+        assert!(!root.children[0].col_data.contains_key("done"));
+    }
+
+    #[test]
+    fn moved_columns_keep_the_cursor_and_the_column_dialog() {
+        let mut app = TreeTablesApp {
+            doc: Document::new(table(parent(vec![leaf(1.0, 1.0)], 1.0)), "move_test.tt"),
+            ..TreeTablesApp::default()
+        };
+        app.doc.nav.cursor = Some(CellCursor {
+            row_path: vec![0],
+            col_idx: 1,
+        });
+        app.edit_column_idx = Some(2);
+        app.move_column(0, 2);
+
+        let ids: Vec<&str> = (app.doc.tree_table.column_configs.iter())
+            .map(|cfg| cfg.id.as_str())
+            .collect();
+        assert_eq!(ids, [SUM, FACTOR, NUMBER]);
+        assert_eq!(app.doc.nav.cursor.as_ref().unwrap().col_idx, 3);
+        assert_eq!(app.edit_column_idx, Some(1));
+        assert!(app.doc.file_modified);
+    }
+
+    #[test]
+    fn column_widths_are_kept_out_of_the_document() {
+        let mut doc = Document::new(table(parent(vec![leaf(1.0, 1.0)], 1.0)), "width_test.tt");
+        doc.tree_table.column_configs[1].width = 200.0;
+        assert!(!doc.tree_table.to_json().contains("width"));
+
+        let (mut expanded_rows, mut column_widths) = (HashMap::new(), HashMap::new());
+        doc.store_view_state(&mut expanded_rows, &mut column_widths);
+        let json = doc.tree_table.to_json();
+        let mut reopened = Document::new(TreeTable::from_json(&json).unwrap(), "width_test.tt");
+        assert_eq!(
+            reopened.tree_table.column_configs[1].width,
+            DEFAULT_COLUMN_WIDTH
+        );
+        reopened.restore_view_state(&expanded_rows, &column_widths);
+        assert_eq!(reopened.tree_table.column_configs[1].width, 200.0);
+    }
+
+    #[test]
+    fn hidden_columns_are_left_out_of_the_export() {
+        let mut table = table(parent(vec![leaf(1.0, 1.0)], 1.0));
+        table.column_configs[1].caption = "Total".to_owned();
+        table.column_configs[1].hidden = true;
+
+        let csv = table.to_csv(false);
+        assert!(!csv.contains("Total"));
+        assert_eq!(csv.lines().next().unwrap().split(';').count(), 6);
+        // The document keeps the hidden column
+        let reopened = TreeTable::from_json(&table.to_json()).unwrap();
+        assert!(reopened.column_configs[1].hidden);
+    }
+
+    #[test]
+    fn table_layout_skips_hidden_columns() {
+        let mut table = table(RowData::default());
+        for (cfg, width) in table.column_configs.iter_mut().zip([100.0, 80.0, 60.0]) {
+            cfg.width = width;
+        }
+        table.column_configs[1].hidden = true;
+        let (columns, width) = TableLayout::shown_columns(&table.column_configs, 4.0);
+        assert_eq!(columns, [(0, 0.0, 100.0), (2, 104.0, 60.0)]);
+        assert_eq!(width, 168.0);
+
+        // Scrolled by 300 to the right and 100 down, with a 150 wide name column
+        let view = Rect::from_min_size(Pos2::ZERO, vec2(800.0, 600.0));
+        let layout = TableLayout {
+            origin: pos2(-300.0, -100.0),
+            view,
+            split: pos2(150.0, 20.0),
+            row_height: 20.0,
+            columns,
+            button_x: width,
+        };
+        let body_rect = layout.body_rect(104.0, 0.0, 60.0);
+        assert_eq!((body_rect.min.x, body_rect.max.x), (-46.0, 14.0));
+        // The cursor column index counts the name column
+        let rect = layout.cell_scroll_rect(2, 3);
+        assert_eq!(
+            rect,
+            Rect::from_min_max(pos2(-300.0, -60.0), pos2(14.0, -20.0))
+        );
+        assert_eq!(layout.cell_scroll_rect(2, 0).max.x, -300.0);
+    }
+
+    #[test]
+    fn recent_files_are_limited_and_most_recent_first() {
+        let mut app = TreeTablesApp::default();
+        for i in 0..12 {
+            app.doc.filename = format!("{i}.tt");
+            app.add_recent_file();
+        }
+        assert_eq!(app.recent_files.len(), MAX_RECENT_FILES);
+        assert_eq!(app.recent_files[0], "11.tt");
+        assert_eq!(app.recent_files[MAX_RECENT_FILES - 1], "2.tt");
+
+        app.doc.filename = "5.tt".to_owned();
+        app.add_recent_file();
+        assert_eq!(app.recent_files.len(), MAX_RECENT_FILES);
+        assert_eq!(app.recent_files[..3], ["5.tt", "11.tt", "10.tt"]);
+    }
+
+    #[test]
+    fn scenarios_override_the_base_values() {
+        let mut table = table(parent(vec![leaf(1.0, 1.0), leaf(2.0, 1.0)], 1.0));
+        let names = ["basic", "premium"].map(|name| Scenario {
+            name: name.to_owned(),
+            ..Scenario::default()
+        });
+        table.scenarios.extend(names);
+
+        table.set_scenario(Some(1));
+        let premium = &mut table.root_row.children;
+        premium[0].col_data.insert(NUMBER.to_owned(), 10.0);
+        premium[1].enabled = false;
+        premium[1].multiplier = 3.0;
+        table.update();
+        assert_eq!(values(&table.root_row)[0], 10.0);
+
+        table.set_scenario(Some(0));
+        table.update();
+        assert_eq!(values(&table.root_row)[0], 3.0);
+        table.set_scenario(None);
+        assert!(table.scenarios[0].overrides.is_empty());
+        assert_eq!(table.scenarios[1].overrides.len(), 2);
+
+        table.set_scenario(Some(1));
+        table.update();
+        let premium = &table.root_row.children;
+        assert_eq!(values(&premium[0])[0], 10.0);
+        assert_eq!((premium[1].enabled, premium[1].multiplier), (false, 3.0));
+        assert_eq!(values(&table.root_row)[0], 10.0);
+        assert_eq!(
+            table
+                .scenario_totals()
+                .iter()
+                .map(|(name, totals)| (name.as_str(), totals[NUMBER]))
+                .collect::<Vec<_>>(),
+            [("Base", 3.0), ("basic", 3.0), ("premium", 10.0)]
+        );
+    }
+
+    #[test]
+    fn renamed_parameters_keep_their_references() {
+        let mut a = parent(vec![leaf(1.0, 1.0)], 1.0);
+        a.multiplier_parameter = Some("count".to_owned());
+        let mut table = table(parent(vec![a], 1.0));
+        table.column_configs[2].col_type =
+            ColumnType::MultiplyByFactor(NUMBER.to_owned(), Factor::Parameter("rate".to_owned()));
+        table.parameters = vec![
+            Parameter {
+                name: "rate".to_owned(),
+                value: 5.0,
+            },
+            Parameter {
+                name: "count".to_owned(),
+                value: 2.0,
+            },
+        ];
+
+        table.parameters[0].name = "hourly_rate".to_owned();
+        table.rename_parameter("rate", "hourly_rate");
+        table.parameters[1].name = "quantity".to_owned();
+        table.rename_parameter("count", "quantity");
+        table.update();
+
+        let a = &table.root_row.children[0];
+        assert_eq!(a.multiplier_parameter.as_deref(), Some("quantity"));
+        assert_eq!(values(a), [1.0, 5.0, 6.0]);
+        assert_eq!(values(&table.root_row), [2.0, 10.0, 12.0]);
+    }
+
+    #[test]
+    fn pinned_leaf_values_replace_derived_values() {
+        let mut pinned = leaf(2.0, 1.0);
+        pinned.pinned.insert(FACTOR.to_owned(), 7.0);
+        let mut table = table(parent(vec![pinned, leaf(1.0, 1.0)], 1.0));
+        table.update();
+
+        let pinned = &table.root_row.children[0];
+        assert_eq!(values(pinned), [2.0, 7.0, 9.0]);
+        assert_eq!(pinned.calculated.get(FACTOR), Some(&20.0));
+        assert_eq!(values(&table.root_row), [3.0, 17.0, 20.0]);
+
+        table.root_row.children[0].pinned.clear();
+        table.root_row.mark_all_dirty();
+        table.update();
+        assert_eq!(values(&table.root_row), [3.0, 30.0, 33.0]);
+    }
+
+    #[test]
+    fn missing_parameters_count_as_zero() {
+        let mut a = parent(vec![leaf(1.0, 1.0)], 1.0);
+        a.multiplier_parameter = Some("count".to_owned());
+        let mut table = table(parent(vec![a, leaf(2.0, 1.0)], 1.0));
+        table.column_configs[2].col_type =
+            ColumnType::MultiplyByFactor(NUMBER.to_owned(), Factor::Parameter("rate".to_owned()));
+        table.column_configs[2].caption = "Price".to_owned();
+        table.root_row.children[0].name = "a".to_owned();
+        assert_eq!(table.parameter_users("rate"), ["Price"]);
+        assert_eq!(table.parameter_users("count"), ["a"]);
+        table.update();
+
+        assert_eq!(table.root_row.children[0].multiplier, 0.0);
+        assert_eq!(values(&table.root_row), [2.0, 0.0, 2.0]);
+    }
+
+    #[test]
+    fn saved_documents_are_stable() {
+        let mut row = leaf(1.0, 1.0);
+        for col_id in ["zeta", "alpha", "mu"] {
+            row.col_data.insert(col_id.to_owned(), 1.0);
+            row.categories.insert(col_id.to_owned(), "x".to_owned());
+        }
+        let mut table = table(parent(vec![row, leaf(2.0, 3.0)], 1.0));
+        table.update();
+
+        let json = table.to_json();
+        assert!(json.ends_with("}\n"));
+        assert!(json.lines().count() > 10);
+        // Saving a loaded document gives the same file
+        let mut reloaded = TreeTable::from_json(&json).unwrap();
+        assert_eq!(reloaded.to_json(), json);
+        reloaded.update();
+        assert_eq!(reloaded.to_json(), json);
+
+        let position = |key: &str| json.find(&format!("\"{key}\": ")).unwrap();
+        assert!(position("alpha") < position("mu"));
+        assert!(position("mu") < position("zeta"));
+    }
+
+    #[test]
+    fn rows_without_id_get_the_same_ids_on_every_load() {
+        let json = r#"{
+            "title_text": "Old",
+            "column_configs": [],
+            "root_row": {
+                "name": "Root",
+                "children": [
+                    {"name": "a", "children": [{"name": "a1"}]},
+                    {"id": "b", "name": "b"}
+                ]
+            }
+        }"#;
+        let table = TreeTable::from_json(json).unwrap();
+        let root = &table.root_row;
+        assert_eq!(root.id, "row");
+        assert_eq!(root.children[0].id, "row/0");
+        assert_eq!(root.children[0].children[0].id, "row/0/0");
+        assert_eq!(root.children[1].id, "b");
+
+        let reloaded = TreeTable::from_json(json).unwrap();
+        assert_eq!(reloaded.root_row.children[0].children[0].id, "row/0/0");
+    }
+}
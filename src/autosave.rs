@@ -0,0 +1,153 @@
+// Recovery copies of the open document, which survive a crash of the app or a power loss.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const APP_ID: &str = "Tree-Tables";
+
+/// The recovery file of a document: next to the document if its directory exists,
+/// otherwise in the app data directory. The ID of the document tells apart the unsaved
+/// documents there, which all have the same name.
+pub fn recovery_path(filename: &str, document_id: &str) -> Option<PathBuf> {
+    let path = Path::new(filename);
+    let file_name = path.file_name()?.to_string_lossy();
+
+    match path.parent() {
+        Some(dir) if path.is_absolute() && dir.is_dir() => {
+            Some(dir.join(format!(".{file_name}.recovery")))
+        }
+        _ => data_dir().map(|dir| dir.join(format!(".{file_name}.{document_id}.recovery"))),
+    }
+}
+
+/// The recovery files in the app data directory with the file name and ID of their document,
+/// e.g. of unsaved documents after a crash.
+pub fn unsaved_recoveries() -> Vec<(PathBuf, String, String)> {
+    data_dir().map_or(vec![], |dir| recoveries_in(&dir))
+}
+
+fn recoveries_in(dir: &Path) -> Vec<(PathBuf, String, String)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut recoveries: Vec<(PathBuf, String, String)> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            let name = name.strip_prefix('.')?.strip_suffix(".recovery")?;
+            let (file_name, document_id) = name.rsplit_once('.')?;
+            Some((path.clone(), file_name.to_owned(), document_id.to_owned()))
+        })
+        .collect();
+    recoveries.sort();
+    recoveries
+}
+
+/// The directory of the app's own files, None on the web.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn data_dir() -> Option<PathBuf> {
+    eframe::storage_dir(APP_ID)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn data_dir() -> Option<PathBuf> {
+    None
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).ok()?.modified().ok()
+}
+
+/// Returns the recovery file of the document if it is newer than the saved document.
+pub fn newer_recovery(filename: &str, document_id: &str) -> Option<PathBuf> {
+    let recovery = recovery_path(filename, document_id)?;
+    let recovery_time = modified_time(&recovery)?;
+
+    match modified_time(Path::new(filename)) {
+        Some(saved_time) if saved_time >= recovery_time => None,
+        _ => Some(recovery),
+    }
+}
+
+pub fn write_recovery(filename: &str, document_id: &str, json: &str) -> io::Result<()> {
+    let path = recovery_path(filename, document_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no recovery directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, json)
+}
+
+pub fn remove_recovery(filename: &str, document_id: &str) {
+    if let Some(path) = recovery_path(filename, document_id) {
+        // The recovery file usually doesn't exist
+        let _res = std::fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tree_tables_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn recovery_file_is_next_to_the_document() {
+        let document = test_dir("recovery_path").join("offer.tt");
+        let recovery = recovery_path(&document.display().to_string(), "id").unwrap();
+        assert_eq!(recovery, document.with_file_name(".offer.tt.recovery"));
+    }
+
+    #[test]
+    fn unsaved_documents_have_their_own_recovery_files() {
+        let first = recovery_path("unnamed.tt", "first");
+        let second = recovery_path("unnamed.tt", "second");
+        assert_ne!(first, second);
+
+        let dir = test_dir("unsaved_recoveries");
+        for path in [first, second].into_iter().flatten() {
+            std::fs::write(dir.join(path.file_name().unwrap()), "{}").unwrap();
+        }
+        std::fs::write(dir.join("app.ron"), "").unwrap();
+        let recoveries = recoveries_in(&dir);
+        let ids: Vec<(&str, &str)> = (recoveries.iter())
+            .map(|(_, file_name, id)| (file_name.as_str(), id.as_str()))
+            .collect();
+        assert_eq!(ids, [("unnamed.tt", "first"), ("unnamed.tt", "second")]);
+    }
+
+    #[test]
+    fn only_newer_recovery_files_are_offered() {
+        let document = test_dir("newer_recovery").join("offer.tt");
+        let filename = document.display().to_string();
+        assert_eq!(newer_recovery(&filename, "id"), None);
+
+        // An unsaved document with a recovery file
+        write_recovery(&filename, "id", "{}").unwrap();
+        let recovery = recovery_path(&filename, "id").unwrap();
+        assert_eq!(newer_recovery(&filename, "id"), Some(recovery.clone()));
+
+        let now = SystemTime::now();
+        std::fs::write(&document, "{}").unwrap();
+        set_modified(&recovery, now - Duration::from_secs(60));
+        set_modified(&document, now);
+        assert_eq!(newer_recovery(&filename, "id"), None);
+
+        set_modified(&recovery, now + Duration::from_secs(60));
+        assert_eq!(newer_recovery(&filename, "id"), Some(recovery));
+
+        remove_recovery(&filename, "id");
+        assert_eq!(newer_recovery(&filename, "id"), None);
+    }
+}
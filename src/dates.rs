@@ -0,0 +1,71 @@
+// Calendar dates, stored as the number of days since 1970-01-01.
+//
+// The conversions follow Howard Hinnant's `days_from_civil` and `civil_from_days`.
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Formats a date as `2024-03-15`.
+pub fn format_date(days: f64) -> String {
+    let (year, month, day) = civil_from_days(days.floor() as i64);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Parses `2024-03-15` or `15.03.2024`.
+pub fn parse_date(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let parts: Vec<i64> = if text.contains('-') {
+        text.split('-')
+            .map(|p| p.parse().ok())
+            .collect::<Option<_>>()?
+    } else {
+        let mut parts: Vec<i64> = text
+            .split('.')
+            .map(|p| p.parse().ok())
+            .collect::<Option<_>>()?;
+        parts.reverse();
+        parts
+    };
+    let [year, month, day] = parts[..] else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Rejects days beyond the end of the month, e.g. 2023-02-30
+    let days = days_from_civil(year, month, day);
+    (civil_from_days(days) == (year, month, day)).then_some(days as f64)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn today() -> f64 {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+    (now.unwrap_or_default().as_secs() / 86400) as f64
+}
+
+// The system time isn't available on the web
+#[cfg(target_arch = "wasm32")]
+pub fn today() -> f64 {
+    0.0
+}
@@ -0,0 +1,499 @@
+// Differences between two tree tables, for the compare view and `tree_tables diff`.
+//
+// Columns are matched by their ID. Rows are matched by their ID among the children of matched
+// rows. Documents without stable row IDs don't even share the ID of their root row, their rows
+// are matched by their position instead. A row which moved to another parent shows up as removed
+// and added.
+
+use std::collections::HashMap;
+
+use crate::app::{format_float, ColumnConfig, RowData, TreeTable};
+
+// Smaller differences are rounding noise of the aggregation
+const EPSILON: f64 = 1e-9;
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum Status {
+    Unchanged,
+    Added,
+    Removed,
+    Renamed,
+    Changed,
+}
+
+impl Status {
+    fn symbol(self) -> char {
+        match self {
+            Status::Unchanged => ' ',
+            Status::Added => '+',
+            Status::Removed => '-',
+            Status::Renamed | Status::Changed => '~',
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ColumnDiff {
+    pub id: String,
+    pub caption: String,
+    pub unit: String,
+    pub status: Status,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_caption: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct ColumnDelta {
+    pub column_id: String,
+    pub old: f64,
+    pub new: f64,
+}
+
+impl ColumnDelta {
+    pub fn delta(&self) -> f64 {
+        self.new - self.old
+    }
+}
+
+// A changed value of a Boolean or Enum column, None if the leaf had none
+#[derive(serde::Serialize, Debug)]
+pub struct CategoryChange {
+    pub column_id: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct RowDiff {
+    pub id: String,
+    pub name: String,
+    pub status: Status,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_name: Option<String>,
+
+    // Changed values. For parent rows these are the aggregated differences of their children.
+    pub deltas: Vec<ColumnDelta>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<CategoryChange>,
+    pub children: Vec<RowDiff>,
+}
+
+impl RowDiff {
+    /// True if the row or any of its descendants changed.
+    pub fn has_changes(&self) -> bool {
+        self.status != Status::Unchanged
+            || !self.deltas.is_empty()
+            || self.children.iter().any(RowDiff::has_changes)
+    }
+
+    pub fn delta(&self, column_id: &str) -> Option<&ColumnDelta> {
+        self.deltas.iter().find(|d| d.column_id == column_id)
+    }
+
+    pub fn category(&self, column_id: &str) -> Option<&CategoryChange> {
+        self.categories.iter().find(|c| c.column_id == column_id)
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct TableDiff {
+    pub columns: Vec<ColumnDiff>,
+    pub root: RowDiff,
+}
+
+impl TableDiff {
+    /// Compares the recalculated values of both tables.
+    pub fn new(old: &TreeTable, new: &TreeTable) -> TableDiff {
+        let mut old = old.clone();
+        let mut new = new.clone();
+        old.update();
+        new.update();
+
+        let columns = diff_columns(&old.column_configs, &new.column_configs);
+        let by_path = old.root_row.id != new.root_row.id;
+        let root = diff_rows(Some(&old.root_row), Some(&new.root_row), &columns, by_path);
+        TableDiff { columns, root }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.iter().all(|c| c.status == Status::Unchanged) && !self.root.has_changes()
+    }
+
+    /// A line per changed column and row. Rows are indented by their depth and show
+    /// their changed values as `caption: old → new (delta)`.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for col in self.columns.iter() {
+            if col.status == Status::Unchanged {
+                continue;
+            }
+            text += &format!(
+                "{} column {} ({})",
+                col.status.symbol(),
+                col.caption,
+                col.unit
+            );
+            if let Some(old_caption) = &col.old_caption {
+                text += &format!(", renamed from {old_caption}");
+            }
+            text += "\n";
+        }
+        self.row_text(&self.root, 0, &mut text);
+        text
+    }
+
+    fn row_text(&self, row: &RowDiff, depth: usize, text: &mut String) {
+        if !row.has_changes() {
+            return;
+        }
+
+        text.push(row.status.symbol());
+        text.push(' ');
+        text.push_str(&"  ".repeat(depth));
+        text.push_str(&row.name);
+        if let Some(old_name) = &row.old_name {
+            text.push_str(&format!(" (renamed from {old_name})"));
+        }
+        for delta in row.deltas.iter() {
+            let Some(col) = self.columns.iter().find(|c| c.id == delta.column_id) else {
+                continue;
+            };
+            text.push_str(&format!(
+                "  {}: {} → {} ({}{})",
+                col.caption,
+                format_float(delta.old, None, true),
+                format_float(delta.new, Some(&col.unit), true),
+                if delta.delta() > 0.0 { "+" } else { "" },
+                format_float(delta.delta(), None, true),
+            ));
+        }
+        for change in row.categories.iter() {
+            let Some(col) = self.columns.iter().find(|c| c.id == change.column_id) else {
+                continue;
+            };
+            text.push_str(&format!(
+                "  {}: {} → {}",
+                col.caption,
+                change.old.as_deref().unwrap_or("–"),
+                change.new.as_deref().unwrap_or("–"),
+            ));
+        }
+        text.push('\n');
+
+        for child in row.children.iter() {
+            self.row_text(child, depth + 1, text);
+        }
+    }
+}
+
+fn diff_columns(old: &[ColumnConfig], new: &[ColumnConfig]) -> Vec<ColumnDiff> {
+    let mut columns: Vec<ColumnDiff> = new
+        .iter()
+        .map(|cfg| {
+            let old_cfg = old.iter().find(|old_cfg| old_cfg.id == cfg.id);
+            let renamed = old_cfg.filter(|old_cfg| old_cfg.caption != cfg.caption);
+            let status = match (old_cfg, renamed) {
+                (None, _) => Status::Added,
+                (Some(_), Some(_)) => Status::Renamed,
+                (Some(old_cfg), None) if old_cfg.col_type != cfg.col_type => Status::Changed,
+                (Some(_), None) => Status::Unchanged,
+            };
+            ColumnDiff {
+                id: cfg.id.clone(),
+                caption: cfg.caption.clone(),
+                unit: cfg.unit.clone(),
+                status,
+                old_caption: renamed.map(|old_cfg| old_cfg.caption.clone()),
+            }
+        })
+        .collect();
+
+    for cfg in old.iter().filter(|cfg| !new.iter().any(|c| c.id == cfg.id)) {
+        columns.push(ColumnDiff {
+            id: cfg.id.clone(),
+            caption: cfg.caption.clone(),
+            unit: cfg.unit.clone(),
+            status: Status::Removed,
+            old_caption: None,
+        });
+    }
+    columns
+}
+
+/// Pairs the old and new children by their ID, or by their position.
+/// The new children come first in their order, followed by the removed ones.
+fn match_children<'a>(
+    old: &'a [RowData],
+    new: &'a [RowData],
+    by_path: bool,
+) -> Vec<(Option<&'a RowData>, Option<&'a RowData>)> {
+    let old_by_id: HashMap<&str, usize> = (old.iter().enumerate())
+        .map(|(idx, row)| (row.id.as_str(), idx))
+        .collect();
+
+    let mut old_matched = vec![false; old.len()];
+    let mut pairs = vec![];
+    for (idx, new_row) in new.iter().enumerate() {
+        let old_idx = if by_path {
+            (idx < old.len()).then_some(idx)
+        } else {
+            old_by_id.get(new_row.id.as_str()).copied()
+        };
+
+        match old_idx {
+            Some(old_idx) => {
+                old_matched[old_idx] = true;
+                pairs.push((Some(&old[old_idx]), Some(new_row)));
+            }
+            None => pairs.push((None, Some(new_row))),
+        }
+    }
+
+    for (old_row, matched) in old.iter().zip(old_matched) {
+        if !matched {
+            pairs.push((Some(old_row), None));
+        }
+    }
+    pairs
+}
+
+fn diff_rows(
+    old: Option<&RowData>,
+    new: Option<&RowData>,
+    columns: &[ColumnDiff],
+    by_path: bool,
+) -> RowDiff {
+    let row = new.or(old).expect("at least one row to compare");
+    let value = |row: Option<&RowData>, col_id: &str| {
+        row.and_then(|row| row.col_data.get(col_id))
+            .copied()
+            .unwrap_or(0.0)
+    };
+
+    let deltas: Vec<ColumnDelta> = columns
+        .iter()
+        .map(|col| ColumnDelta {
+            column_id: col.id.clone(),
+            old: value(old, &col.id),
+            new: value(new, &col.id),
+        })
+        .filter(|delta| delta.delta().abs() > EPSILON)
+        .collect();
+
+    // Only leaves have categories
+    let category = |row: Option<&RowData>, col_id: &str| {
+        row.and_then(|row| row.categories.get(col_id)).cloned()
+    };
+    let categories: Vec<CategoryChange> = columns
+        .iter()
+        .map(|col| CategoryChange {
+            column_id: col.id.clone(),
+            old: category(old, &col.id),
+            new: category(new, &col.id),
+        })
+        .filter(|change| change.old != change.new)
+        .collect();
+
+    let old_name = match (old, new) {
+        (Some(old), Some(new)) if old.name != new.name => Some(old.name.clone()),
+        _ => None,
+    };
+    let status = match (old, new) {
+        (None, _) => Status::Added,
+        (_, None) => Status::Removed,
+        (Some(old), Some(new)) => {
+            // The values of parent rows only change with their children
+            let values_changed =
+                (new.children.is_empty() && !deltas.is_empty()) || !categories.is_empty();
+            if values_changed || old.enabled != new.enabled || old.multiplier != new.multiplier {
+                Status::Changed
+            } else if old_name.is_some() {
+                Status::Renamed
+            } else {
+                Status::Unchanged
+            }
+        }
+    };
+
+    let no_children: &[RowData] = &[];
+    let children = match_children(
+        old.map_or(no_children, |row| &row.children),
+        new.map_or(no_children, |row| &row.children),
+        by_path,
+    )
+    .into_iter()
+    .map(|(old, new)| diff_rows(old, new, columns, by_path))
+    .collect();
+
+    RowDiff {
+        id: row.id.clone(),
+        name: row.name.clone(),
+        status,
+        old_name,
+        deltas,
+        categories,
+        children,
+    }
+}
+
+/// Loads both files and compares them.
+pub fn diff_files(old_path: &str, new_path: &str) -> Result<TableDiff, String> {
+    let load = |path: &str| {
+        TreeTable::load_from_file(path).ok_or_else(|| format!("Could not open {path}"))
+    };
+    Ok(TableDiff::new(&load(old_path)?, &load(new_path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::ColumnType;
+
+    const NUMBER: &str = "number";
+
+    fn row(id: &str, number: f64, children: Vec<RowData>) -> RowData {
+        let mut row = RowData::default();
+        row.id = id.to_owned();
+        row.name = id.to_owned();
+        if children.is_empty() {
+            row.col_data.insert(NUMBER.to_owned(), number);
+        }
+        row.children = children;
+        row
+    }
+
+    fn table(children: Vec<RowData>) -> TreeTable {
+        let mut column = ColumnConfig::default();
+        column.id = NUMBER.to_owned();
+        column.caption = "Number".to_owned();
+        column.col_type = ColumnType::Number;
+        TreeTable {
+            title_text: "".to_owned(),
+            column_configs: vec![column],
+            root_row: row("root", 0.0, children),
+            conflicts: vec![],
+            scenarios: vec![],
+            active_scenario: None,
+            base_values: Default::default(),
+            parameters: vec![],
+            exchange_rates: vec![],
+        }
+    }
+
+    // a (1, 2), b (3)
+    fn old() -> TreeTable {
+        table(vec![
+            row("a", 0.0, vec![row("1", 1.0, vec![]), row("2", 2.0, vec![])]),
+            row("b", 0.0, vec![row("3", 3.0, vec![])]),
+        ])
+    }
+
+    fn statuses(row: &RowDiff) -> Vec<(&str, Status)> {
+        (row.children.iter())
+            .map(|child| (child.name.as_str(), child.status))
+            .collect()
+    }
+
+    fn delta(row: &RowDiff) -> Option<f64> {
+        row.delta(NUMBER).map(ColumnDelta::delta)
+    }
+
+    #[test]
+    fn rows_are_matched_by_id() {
+        let mut new = old();
+        new.root_row.children.swap(0, 1);
+        let old = old();
+        let pairs = match_children(&old.root_row.children, &new.root_row.children, false);
+        let ids: Vec<[&str; 2]> = (pairs.iter())
+            .map(|(old, new)| [old.unwrap().id.as_str(), new.unwrap().id.as_str()])
+            .collect();
+        assert_eq!(ids, [["b", "b"], ["a", "a"]]);
+
+        assert!(TableDiff::new(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn rows_without_stable_ids_are_matched_by_path() {
+        fn new_ids(row: &mut RowData) {
+            row.id = format!("new-{}", row.id);
+            row.children.iter_mut().for_each(new_ids);
+        }
+        let mut new = old();
+        new_ids(&mut new.root_row);
+        new.root_row.children[1].children[0]
+            .col_data
+            .insert(NUMBER.to_owned(), 5.0);
+
+        let diff = TableDiff::new(&old(), &new);
+        assert_eq!(
+            statuses(&diff.root),
+            [("a", Status::Unchanged), ("b", Status::Unchanged)]
+        );
+        let b = &diff.root.children[1];
+        assert_eq!(statuses(b), [("3", Status::Changed)]);
+        assert_eq!(delta(&b.children[0]), Some(2.0));
+    }
+
+    #[test]
+    fn renamed_rows_keep_their_old_name() {
+        let mut new = old();
+        new.root_row.children[1].name = "B".to_owned();
+
+        let diff = TableDiff::new(&old(), &new);
+        let b = &diff.root.children[1];
+        assert_eq!(b.status, Status::Renamed);
+        assert_eq!(b.old_name.as_deref(), Some("b"));
+        assert!(diff.to_text().contains("B (renamed from b)"));
+    }
+
+    #[test]
+    fn deltas_of_parents_are_aggregated() {
+        let mut new = old();
+        new.root_row.children[0].children[0]
+            .col_data
+            .insert(NUMBER.to_owned(), 4.0);
+        new.root_row.children[0].children[1].multiplier = 2.0;
+
+        let diff = TableDiff::new(&old(), &new);
+        let a = &diff.root.children[0];
+        // The values of parents only change with their children
+        assert_eq!(a.status, Status::Unchanged);
+        assert_eq!(delta(a), Some(5.0));
+        assert_eq!(delta(&diff.root), Some(5.0));
+        assert_eq!(
+            statuses(a),
+            [("1", Status::Changed), ("2", Status::Changed)]
+        );
+        // The values of a leaf are those of a single unit
+        assert_eq!(delta(&a.children[1]), None);
+        assert_eq!(delta(&diff.root.children[1]), None);
+    }
+
+    #[test]
+    fn added_rows_come_before_removed_ones() {
+        let mut new = old();
+        new.root_row.children.remove(0);
+        new.root_row.children.push(row("c", 10.0, vec![]));
+
+        let diff = TableDiff::new(&old(), &new);
+        assert_eq!(
+            statuses(&diff.root),
+            [
+                ("b", Status::Unchanged),
+                ("c", Status::Added),
+                ("a", Status::Removed)
+            ]
+        );
+        assert_eq!(delta(&diff.root.children[1]), Some(10.0));
+        assert_eq!(delta(&diff.root.children[2]), Some(-3.0));
+        assert_eq!(delta(&diff.root), Some(7.0));
+        let text = diff.to_text();
+        let names: Vec<&str> = (text.lines())
+            .map(|line| line.split("  Number").next().unwrap())
+            .collect();
+        assert_eq!(names, ["  root", "+   c", "-   a", "-     1", "-     2"]);
+    }
+}
@@ -1,4 +1,12 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
-pub use app::TreeTablesApp;
+mod autosave;
+mod dates;
+mod diff;
+mod library;
+mod merge;
+mod units;
+pub use app::{TreeTable, TreeTablesApp};
+pub use diff::{diff_files, TableDiff};
+pub use merge::{merge_files, Conflict};
@@ -0,0 +1,55 @@
+// The library of row templates, subtrees which are reused across documents, e.g. a standard
+// component with its parts and prices. It is stored in the app data directory.
+
+use std::io;
+use std::path::PathBuf;
+
+use crate::app::RowData;
+use crate::autosave::data_dir;
+
+/// A saved subtree, its root row holds the name and the values of a single unit.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Template {
+    pub id: String,
+    pub row: RowData,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Library {
+    pub templates: Vec<Template>,
+}
+
+fn library_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("templates.json"))
+}
+
+impl Library {
+    /// Reads the library file, an empty library if there is none.
+    pub fn load() -> io::Result<Library> {
+        let Some(path) = library_path() else {
+            return Ok(Library::default());
+        };
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Library::default()),
+            Err(err) => return Err(err),
+        };
+        serde_json::from_str(&json).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = library_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no library directory"))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        std::fs::write(path, json)
+    }
+
+    pub fn find(&self, id: &str) -> Option<&Template> {
+        self.templates.iter().find(|template| template.id == id)
+    }
+}
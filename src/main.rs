@@ -6,6 +6,14 @@
 fn main() -> eframe::Result<()> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
 
+    // Command line tools, which don't open a window
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("diff") => std::process::exit(diff_command(&args[2..])),
+        Some("merge") => std::process::exit(merge_command(&args[2..])),
+        _ => {}
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([400.0, 300.0])
@@ -24,6 +32,69 @@ fn main() -> eframe::Result<()> {
     )
 }
 
+// `tree_tables diff [--json] OLD NEW`, exits with 0 if the tables are equal, 1 if they differ
+// and 2 on errors like `diff` does
+#[cfg(not(target_arch = "wasm32"))]
+fn diff_command(args: &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let files: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let [old_path, new_path] = files[..] else {
+        eprintln!("Usage: tree_tables diff [--json] OLD NEW");
+        return 2;
+    };
+
+    let diff = match tree_tables::diff_files(old_path, new_path) {
+        Ok(diff) => diff,
+        Err(err) => {
+            eprintln!("{err}");
+            return 2;
+        }
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff).unwrap());
+    } else {
+        print!("{}", diff.to_text());
+    }
+    if diff.is_empty() {
+        0
+    } else {
+        1
+    }
+}
+
+// `tree_tables merge BASE OURS THEIRS [OUTPUT]` as git merge driver: the result is written to
+// OURS unless an output is given. Exits with 1 if there are conflicts, which are kept in the file
+// and can be resolved in the app.
+#[cfg(not(target_arch = "wasm32"))]
+fn merge_command(args: &[String]) -> i32 {
+    let (base, ours, theirs, output) = match args {
+        [base, ours, theirs] => (base, ours, theirs, ours),
+        [base, ours, theirs, output] => (base, ours, theirs, output),
+        _ => {
+            eprintln!("Usage: tree_tables merge BASE OURS THEIRS [OUTPUT]");
+            return 2;
+        }
+    };
+
+    match tree_tables::merge_files(base, ours, theirs, output) {
+        Ok(conflicts) if conflicts.is_empty() => 0,
+        Ok(conflicts) => {
+            for conflict in conflicts.iter() {
+                eprintln!(
+                    "Conflict in {} ({:?}): ours {}, theirs {}",
+                    conflict.location, conflict.field, conflict.ours, conflict.theirs
+                );
+            }
+            1
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            2
+        }
+    }
+}
+
 // When compiling to web using trunk:
 #[cfg(target_arch = "wasm32")]
 fn main() {
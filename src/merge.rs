@@ -0,0 +1,992 @@
+// Three-way merge of tree tables, for `tree_tables merge` as git merge driver and the merge view.
+//
+// Rows and columns are matched by their IDs. Files without stable row IDs match their rows by
+// their paths instead, like the diff does. A change of only one side is taken over, different
+// changes of both sides to the same field are conflicts. The merged table holds our value of each
+// conflict, and keeps a row or column which one side removed while the other side changed it.
+// The conflicts are stored in the table, so they can be resolved in the app after a merge on the
+// command line.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use serde_json::{json, Value};
+
+use crate::app::{ColumnConfig, RowData, TreeTable};
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+pub enum ConflictField {
+    Title,
+    Caption,
+    Unit,
+    ColumnType,
+    // Whether the column still exists
+    ColumnKept,
+    Name,
+    Enabled,
+    Multiplier,
+    MultiplierParameter,
+    Value(String),
+    Quantity(String),
+    Category(String),
+    Pinned(String),
+    Notes,
+    Tags,
+    Links,
+    Template,
+    // The ID of the parent row
+    Parent,
+    // Whether the row still exists
+    RowKept,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+pub struct Conflict {
+    // The ID of the row or column
+    pub id: String,
+    pub field: ConflictField,
+
+    // The names of the row and its ancestors, or the caption of the column
+    pub location: String,
+
+    pub base: Value,
+    pub ours: Value,
+    pub theirs: Value,
+}
+
+// A row of a flattened tree, the children are looked up by the IDs of their parent
+struct FlatRow<'a> {
+    row: &'a RowData,
+    parent: Option<&'a str>,
+}
+
+struct FlatTree<'a> {
+    rows: HashMap<&'a str, FlatRow<'a>>,
+
+    // All IDs in tree order
+    order: Vec<&'a str>,
+}
+
+impl<'a> FlatTree<'a> {
+    fn new(root: &'a RowData) -> Self {
+        let mut tree = FlatTree {
+            rows: HashMap::new(),
+            order: vec![],
+        };
+        tree.add(root, None);
+        tree
+    }
+
+    fn add(&mut self, row: &'a RowData, parent: Option<&'a str>) {
+        self.rows.insert(&row.id, FlatRow { row, parent });
+        self.order.push(&row.id);
+        for child in row.children.iter() {
+            self.add(child, Some(&row.id));
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<&FlatRow<'a>> {
+        self.rows.get(id)
+    }
+
+    fn location(&self, id: &str) -> String {
+        let mut names = vec![];
+        let mut current = self.get(id);
+        while let Some(flat) = current {
+            names.push(flat.row.name.as_str());
+            current = flat.parent.and_then(|parent| self.get(parent));
+        }
+        names.reverse();
+        names.join(" / ")
+    }
+}
+
+// The location of the row in the first tree which has it
+fn location(trees: [&FlatTree<'_>; 3], id: &str) -> String {
+    let tree = trees
+        .into_iter()
+        .find(|tree| tree.get(id).is_some())
+        .unwrap();
+    tree.location(id)
+}
+
+// The fields of a row itself, without its children
+fn same_row(a: &FlatRow<'_>, b: &FlatRow<'_>) -> bool {
+    a.parent == b.parent
+        && a.row.name == b.row.name
+        && a.row.enabled == b.row.enabled
+        && a.row.multiplier == b.row.multiplier
+        && a.row.multiplier_parameter == b.row.multiplier_parameter
+        && (!a.row.children.is_empty() || a.row.col_data == b.row.col_data)
+        && a.row.quantities == b.row.quantities
+        && a.row.categories == b.row.categories
+        && a.row.pinned == b.row.pinned
+        && a.row.notes == b.row.notes
+        && a.row.tags == b.row.tags
+        && a.row.links == b.row.links
+        && a.row.template_id == b.row.template_id
+}
+
+fn same_column(a: &ColumnConfig, b: &ColumnConfig) -> bool {
+    a.caption == b.caption && a.unit == b.unit && a.col_type == b.col_type
+}
+
+struct Merger<'a> {
+    base: FlatTree<'a>,
+    ours: FlatTree<'a>,
+    theirs: FlatTree<'a>,
+    conflicts: Vec<Conflict>,
+}
+
+// Takes the changed side, or ours and a conflict if both sides changed
+fn merge_field<T: PartialEq + Clone + serde::Serialize>(
+    conflicts: &mut Vec<Conflict>,
+    id: &str,
+    field: ConflictField,
+    location: impl FnOnce() -> String,
+    [base, ours, theirs]: [&T; 3],
+) -> T {
+    if ours == theirs || theirs == base {
+        return ours.clone();
+    }
+    if ours == base {
+        return theirs.clone();
+    }
+    conflicts.push(Conflict {
+        id: id.to_owned(),
+        field,
+        location: location(),
+        base: json!(base),
+        ours: json!(ours),
+        theirs: json!(theirs),
+    });
+    ours.clone()
+}
+
+// Merges a map of values per column entry by entry
+fn merge_map<T: PartialEq + Clone + serde::Serialize>(
+    conflicts: &mut Vec<Conflict>,
+    id: &str,
+    field: fn(String) -> ConflictField,
+    location: impl FnOnce() -> String + Copy,
+    maps: [&BTreeMap<String, T>; 3],
+) -> BTreeMap<String, T> {
+    let col_ids: BTreeSet<&String> = maps.iter().flat_map(|map| map.keys()).collect();
+    let mut merged = BTreeMap::new();
+    for col_id in col_ids {
+        let [b, o, t] = maps.map(|map| map.get(col_id).cloned());
+        let value = merge_field(conflicts, id, field(col_id.clone()), location, [&b, &o, &t]);
+        if let Some(value) = value {
+            merged.insert(col_id.clone(), value);
+        }
+    }
+    merged
+}
+
+impl<'a> Merger<'a> {
+    fn columns<'b>(
+        &mut self,
+        base: &'b [ColumnConfig],
+        ours: &'b [ColumnConfig],
+        theirs: &'b [ColumnConfig],
+    ) -> Vec<ColumnConfig> {
+        let find = |columns: &'b [ColumnConfig], id: &str| columns.iter().find(|c| c.id == id);
+        let mut ids: Vec<&str> = ours.iter().map(|c| c.id.as_str()).collect();
+        for cfg in theirs.iter() {
+            if !ids.contains(&cfg.id.as_str()) {
+                ids.push(&cfg.id);
+            }
+        }
+
+        let mut merged = vec![];
+        for id in ids {
+            let location = || find(ours, id).or(find(theirs, id)).unwrap().caption.clone();
+            match (find(base, id), find(ours, id), find(theirs, id)) {
+                (Some(b), Some(o), Some(t)) => {
+                    // The view settings like the width are ours
+                    let mut cfg = o.clone();
+                    let conflicts = &mut self.conflicts;
+                    cfg.caption = merge_field(
+                        conflicts,
+                        id,
+                        ConflictField::Caption,
+                        location,
+                        [&b.caption, &o.caption, &t.caption],
+                    );
+                    cfg.unit = merge_field(
+                        conflicts,
+                        id,
+                        ConflictField::Unit,
+                        location,
+                        [&b.unit, &o.unit, &t.unit],
+                    );
+                    cfg.col_type = merge_field(
+                        conflicts,
+                        id,
+                        ConflictField::ColumnType,
+                        location,
+                        [&b.col_type, &o.col_type, &t.col_type],
+                    );
+                    merged.push(cfg);
+                }
+                (None, Some(cfg), _) | (None, None, Some(cfg)) => merged.push(cfg.clone()),
+                (Some(b), Some(kept), None) | (Some(b), None, Some(kept)) => {
+                    if !same_column(b, kept) {
+                        let kept_by_ours = find(ours, id).is_some();
+                        self.conflicts.push(Conflict {
+                            id: id.to_owned(),
+                            field: ConflictField::ColumnKept,
+                            location: location(),
+                            base: json!(true),
+                            ours: json!(kept_by_ours),
+                            theirs: json!(!kept_by_ours),
+                        });
+                        merged.push(kept.clone());
+                    }
+                }
+                (_, None, None) => {}
+            }
+        }
+        merged
+    }
+
+    // True if the side added or changed the row or any row below it
+    fn subtree_changed(&self, side: &FlatTree<'_>, id: &str) -> bool {
+        let Some(flat) = side.get(id) else {
+            return false;
+        };
+        let changed = match self.base.get(id) {
+            Some(base) => !same_row(base, flat),
+            None => true,
+        };
+        changed || (flat.row.children.iter()).any(|child| self.subtree_changed(side, &child.id))
+    }
+
+    // Merges the fields of the row with the given ID, returns the merged row without children
+    // and its parent ID, or None if the row was removed
+    fn row(&mut self, id: &str, editable: &HashSet<&str>) -> Option<(RowData, Option<String>)> {
+        let location = || location([&self.ours, &self.theirs, &self.base], id);
+
+        let (base, ours, theirs) = (self.base.get(id), self.ours.get(id), self.theirs.get(id));
+        let (b, o, t) = match (base, ours, theirs) {
+            (Some(b), Some(o), Some(t)) => (b, o, t),
+            (None, Some(added), _) | (None, None, Some(added)) => {
+                let parent = added.parent.map(str::to_owned);
+                return Some((added.row.without_children(), parent));
+            }
+            (Some(_), Some(kept), None) | (Some(_), None, Some(kept)) => {
+                let kept_by_ours = ours.is_some();
+                let side = if kept_by_ours {
+                    &self.ours
+                } else {
+                    &self.theirs
+                };
+                if !self.subtree_changed(side, id) {
+                    return None;
+                }
+
+                let conflict = Conflict {
+                    id: id.to_owned(),
+                    field: ConflictField::RowKept,
+                    location: location(),
+                    base: json!(true),
+                    ours: json!(kept_by_ours),
+                    theirs: json!(!kept_by_ours),
+                };
+                let parent = kept.parent.map(str::to_owned);
+                let row = kept.row.without_children();
+                self.conflicts.push(conflict);
+                return Some((row, parent));
+            }
+            (_, None, None) => return None,
+        };
+
+        let (b, o, t) = (b.row, o.row, t.row);
+        let [bp, op, tp] = [base, ours, theirs].map(|flat| flat.unwrap().parent);
+        let parent = merge_field(
+            &mut self.conflicts,
+            id,
+            ConflictField::Parent,
+            location,
+            [&bp, &op, &tp],
+        );
+
+        let mut row = o.without_children();
+        row.name = merge_field(
+            &mut self.conflicts,
+            id,
+            ConflictField::Name,
+            location,
+            [&b.name, &o.name, &t.name],
+        );
+        row.enabled = merge_field(
+            &mut self.conflicts,
+            id,
+            ConflictField::Enabled,
+            location,
+            [&b.enabled, &o.enabled, &t.enabled],
+        );
+        row.multiplier = merge_field(
+            &mut self.conflicts,
+            id,
+            ConflictField::Multiplier,
+            location,
+            [&b.multiplier, &o.multiplier, &t.multiplier],
+        );
+        row.multiplier_parameter = merge_field(
+            &mut self.conflicts,
+            id,
+            ConflictField::MultiplierParameter,
+            location,
+            [
+                &b.multiplier_parameter,
+                &o.multiplier_parameter,
+                &t.multiplier_parameter,
+            ],
+        );
+
+        // The values of parent rows and calculated columns are recalculated anyway
+        if o.children.is_empty() && t.children.is_empty() {
+            for col_id in editable.iter() {
+                let [bv, ov, tv] = [b, o, t].map(|row| row.col_data.get(*col_id).copied());
+                let field = ConflictField::Value(col_id.to_string());
+                match merge_field(&mut self.conflicts, id, field, location, [&bv, &ov, &tv]) {
+                    Some(value) => row.col_data.insert(col_id.to_string(), value),
+                    None => row.col_data.remove(*col_id),
+                };
+            }
+        }
+        row.quantities = merge_map(
+            &mut self.conflicts,
+            id,
+            ConflictField::Quantity,
+            location,
+            [&b.quantities, &o.quantities, &t.quantities],
+        );
+        row.categories = merge_map(
+            &mut self.conflicts,
+            id,
+            ConflictField::Category,
+            location,
+            [&b.categories, &o.categories, &t.categories],
+        );
+        row.pinned = merge_map(
+            &mut self.conflicts,
+            id,
+            ConflictField::Pinned,
+            location,
+            [&b.pinned, &o.pinned, &t.pinned],
+        );
+        row.notes = merge_field(
+            &mut self.conflicts,
+            id,
+            ConflictField::Notes,
+            location,
+            [&b.notes, &o.notes, &t.notes],
+        );
+        row.tags = merge_field(
+            &mut self.conflicts,
+            id,
+            ConflictField::Tags,
+            location,
+            [&b.tags, &o.tags, &t.tags],
+        );
+        row.links = merge_field(
+            &mut self.conflicts,
+            id,
+            ConflictField::Links,
+            location,
+            [&b.links, &o.links, &t.links],
+        );
+        row.template_id = merge_field(
+            &mut self.conflicts,
+            id,
+            ConflictField::Template,
+            location,
+            [&b.template_id, &o.template_id, &t.template_id],
+        );
+
+        Some((row, parent.map(str::to_owned)))
+    }
+
+    // The order of the children: ours, and the other ones after their previous sibling of theirs
+    fn child_order(&self, parent: &str, children: &HashSet<&str>) -> Vec<String> {
+        let side_children = |tree: &FlatTree<'_>| -> Vec<String> {
+            tree.get(parent)
+                .map(|flat| flat.row.children.iter().map(|c| c.id.clone()).collect())
+                .unwrap_or_default()
+        };
+
+        let mut order: Vec<String> = side_children(&self.ours)
+            .into_iter()
+            .filter(|id| children.contains(id.as_str()))
+            .collect();
+        let mut previous: Option<String> = None;
+        for id in side_children(&self.theirs) {
+            if children.contains(id.as_str()) && !order.contains(&id) {
+                let pos = previous
+                    .as_ref()
+                    .and_then(|prev| order.iter().position(|o| o == prev))
+                    .map_or(0, |pos| pos + 1);
+                order.insert(pos, id.clone());
+            }
+            previous = Some(id);
+        }
+
+        // Rows which moved here from elsewhere
+        let mut rest: Vec<&&str> = children
+            .iter()
+            .filter(|id| !order.iter().any(|o| o == **id))
+            .collect();
+        rest.sort();
+        order.extend(rest.into_iter().map(|id| id.to_string()));
+        order
+    }
+}
+
+fn build_row(
+    id: &str,
+    rows: &mut HashMap<String, RowData>,
+    children: &HashMap<String, Vec<String>>,
+) -> RowData {
+    let mut row = rows.remove(id).unwrap();
+    for child_id in children.get(id).into_iter().flatten() {
+        row.children.push(build_row(child_id, rows, children));
+    }
+    row
+}
+
+// Gives the rows the IDs of the rows of ours at the same paths
+fn adopt_ids(row: &mut RowData, ours: &RowData) {
+    row.id = ours.id.clone();
+    for (child, ours_child) in row.children.iter_mut().zip(ours.children.iter()) {
+        adopt_ids(child, ours_child);
+    }
+}
+
+// The rows are matched by their paths if the root rows have different IDs
+fn matched_rows<'a>(table: &'a TreeTable, ours: &TreeTable) -> Cow<'a, RowData> {
+    if table.root_row.id == ours.root_row.id {
+        return Cow::Borrowed(&table.root_row);
+    }
+    let mut root_row = table.root_row.clone();
+    adopt_ids(&mut root_row, &ours.root_row);
+    Cow::Owned(root_row)
+}
+
+// The rows of a cycle of parents, which moves of both sides can form
+fn find_cycle(order: &[String], parents: &HashMap<String, Option<String>>) -> Option<Vec<String>> {
+    // Rows whose ancestors are known to be free of cycles
+    let mut done: HashSet<&str> = HashSet::new();
+    for start in order.iter() {
+        let mut chain: Vec<&str> = vec![];
+        let mut current = Some(start.as_str());
+        while let Some(id) = current {
+            if done.contains(id) {
+                break;
+            }
+            if let Some(pos) = chain.iter().position(|row| *row == id) {
+                return Some(chain[pos..].iter().map(|id| id.to_string()).collect());
+            }
+            chain.push(id);
+            current = parents.get(id).and_then(|parent| parent.as_deref());
+        }
+        done.extend(chain);
+    }
+    None
+}
+
+/// Merges the changes of ours and theirs since base.
+pub fn merge(base: &TreeTable, ours: &TreeTable, theirs: &TreeTable) -> TreeTable {
+    let (base_root, theirs_root) = (matched_rows(base, ours), matched_rows(theirs, ours));
+    let mut merger = Merger {
+        base: FlatTree::new(&base_root),
+        ours: FlatTree::new(&ours.root_row),
+        theirs: FlatTree::new(&theirs_root),
+        conflicts: vec![],
+    };
+
+    let title_text = merge_field(
+        &mut merger.conflicts,
+        "",
+        ConflictField::Title,
+        || "Title".to_owned(),
+        [&base.title_text, &ours.title_text, &theirs.title_text],
+    );
+    let column_configs = merger.columns(
+        &base.column_configs,
+        &ours.column_configs,
+        &theirs.column_configs,
+    );
+    let editable: HashSet<&str> = column_configs
+        .iter()
+        .filter(|cfg| cfg.col_type.is_editable())
+        .map(|cfg| cfg.id.as_str())
+        .collect();
+
+    let mut ids: Vec<&str> = vec![];
+    let mut seen = HashSet::new();
+    for tree in [&merger.ours, &merger.theirs, &merger.base] {
+        for id in tree.order.iter() {
+            if seen.insert(*id) {
+                ids.push(id);
+            }
+        }
+    }
+
+    let root_id = ours.root_row.id.clone();
+    let mut rows = HashMap::new();
+    let mut parents = HashMap::new();
+    let mut order = vec![];
+    for id in ids {
+        if let Some((row, parent)) = merger.row(id, &editable) {
+            parents.insert(id.to_owned(), parent);
+            rows.insert(id.to_owned(), row);
+            order.push(id.to_owned());
+        }
+    }
+
+    // E.g. ours moved A below B and theirs moved B below A, the rows of a cycle keep our parent
+    while let Some(cycle) = find_cycle(&order, &parents) {
+        for id in cycle {
+            let parent_of = |tree: &FlatTree<'_>| {
+                (tree.get(&id)).and_then(|flat| flat.parent.map(str::to_owned))
+            };
+            let ours_parent = parent_of(&merger.ours);
+            let merged_parent = parents.insert(id.clone(), ours_parent.clone()).flatten();
+            if merged_parent != ours_parent {
+                merger.conflicts.push(Conflict {
+                    id: id.clone(),
+                    field: ConflictField::Parent,
+                    location: location([&merger.ours, &merger.theirs, &merger.base], &id),
+                    base: json!(parent_of(&merger.base)),
+                    ours: json!(ours_parent),
+                    theirs: json!(parent_of(&merger.theirs)),
+                });
+            }
+        }
+    }
+
+    // Rows whose parent was removed end up at the top level
+    let mut children: HashMap<String, HashSet<&str>> = HashMap::new();
+    for (id, parent) in parents.iter() {
+        if *id == root_id {
+            continue;
+        }
+        let parent = match parent {
+            Some(parent) if rows.contains_key(parent) => parent.clone(),
+            _ => root_id.clone(),
+        };
+        children.entry(parent).or_default().insert(id);
+    }
+    let children: HashMap<String, Vec<String>> = children
+        .iter()
+        .map(|(parent, ids)| (parent.clone(), merger.child_order(parent, ids)))
+        .collect();
+
+    // The rows are copies of the three sides, their aggregated values are recalculated
+    let mut root_row = build_row(&root_id, &mut rows, &children);
+    root_row.mark_all_dirty();
+
+    TreeTable {
+        title_text,
+        column_configs,
+        root_row,
+        conflicts: merger.conflicts,
+        // Scenarios, parameters and exchange rates aren't merged, ours are kept
+        scenarios: ours.scenarios.clone(),
+        active_scenario: None,
+        base_values: BTreeMap::new(),
+        parameters: ours.parameters.clone(),
+        exchange_rates: ours.exchange_rates.clone(),
+    }
+}
+
+impl TreeTable {
+    /// Resolves a conflict of a merge by taking the value of ours or theirs.
+    pub(crate) fn resolve_conflict(&mut self, idx: usize, use_theirs: bool) {
+        let conflict = self.conflicts.remove(idx);
+        let value = if use_theirs {
+            conflict.theirs
+        } else {
+            conflict.ours
+        };
+        let id = conflict.id.as_str();
+
+        let column = self.column_configs.iter_mut().find(|c| c.id == id);
+        match (conflict.field, column) {
+            (ConflictField::Title, _) => {
+                self.title_text = serde_json::from_value(value).unwrap_or_default();
+            }
+            (ConflictField::Caption, Some(cfg)) => {
+                cfg.caption = serde_json::from_value(value).unwrap_or_default();
+            }
+            (ConflictField::Unit, Some(cfg)) => {
+                cfg.unit = serde_json::from_value(value).unwrap_or_default();
+            }
+            (ConflictField::ColumnType, Some(cfg)) => {
+                if let Ok(col_type) = serde_json::from_value(value) {
+                    cfg.col_type = col_type;
+                }
+            }
+            (ConflictField::ColumnKept, _) => {
+                if value == json!(false) {
+                    self.column_configs.retain(|c| c.id != id);
+                }
+            }
+            (field, _) => self.resolve_row_conflict(id, field, value),
+        }
+    }
+
+    fn resolve_row_conflict(&mut self, id: &str, field: ConflictField, value: Value) {
+        let Some(row_path) = self.root_row.find_path(&|row| row.id == id) else {
+            return;
+        };
+
+        match field {
+            ConflictField::RowKept => {
+                if value == json!(false) {
+                    self.root_row.remove_row(&row_path);
+                }
+            }
+            ConflictField::Parent => {
+                let Some(parent_id) = value.as_str() else {
+                    return;
+                };
+                let find_parent = |root: &RowData| root.find_path(&|row| row.id == parent_id);
+                // A row can't be moved below itself
+                if find_parent(&self.root_row).is_none_or(|p| p.starts_with(&row_path)) {
+                    return;
+                }
+
+                let row = self.root_row.remove_row(&row_path).unwrap();
+                // Removing the row may have shifted the path of the new parent
+                let parent_path = find_parent(&self.root_row).unwrap();
+                let parent = self.root_row.get_row_mut(&parent_path).unwrap();
+                parent.children.push(row);
+            }
+            field => {
+                let row = self.root_row.get_row_mut(&row_path).unwrap();
+                match field {
+                    ConflictField::Name => {
+                        row.name = serde_json::from_value(value).unwrap_or_default();
+                    }
+                    ConflictField::Enabled => {
+                        row.enabled = serde_json::from_value(value).unwrap_or(true);
+                    }
+                    ConflictField::Multiplier => {
+                        row.multiplier = serde_json::from_value(value).unwrap_or(1.0);
+                    }
+                    ConflictField::MultiplierParameter => {
+                        row.multiplier_parameter =
+                            serde_json::from_value(value).unwrap_or_default();
+                    }
+                    ConflictField::Value(col_id) => match value.as_f64() {
+                        Some(x) => {
+                            row.col_data.insert(col_id, x);
+                        }
+                        None => {
+                            row.col_data.remove(&col_id);
+                        }
+                    },
+                    ConflictField::Quantity(col_id) => match serde_json::from_value(value) {
+                        Ok(quantity) => {
+                            row.quantities.insert(col_id, quantity);
+                        }
+                        Err(_) => {
+                            row.quantities.remove(&col_id);
+                        }
+                    },
+                    ConflictField::Category(col_id) => match value.as_str() {
+                        Some(category) => {
+                            row.categories.insert(col_id, category.to_owned());
+                        }
+                        None => {
+                            row.categories.remove(&col_id);
+                        }
+                    },
+                    ConflictField::Pinned(col_id) => match value.as_f64() {
+                        Some(x) => {
+                            row.pinned.insert(col_id, x);
+                        }
+                        None => {
+                            row.pinned.remove(&col_id);
+                        }
+                    },
+                    ConflictField::Notes => {
+                        row.notes = serde_json::from_value(value).unwrap_or_default();
+                    }
+                    ConflictField::Tags => {
+                        row.tags = serde_json::from_value(value).unwrap_or_default();
+                    }
+                    ConflictField::Links => {
+                        row.links = serde_json::from_value(value).unwrap_or_default();
+                    }
+                    ConflictField::Template => {
+                        row.template_id = serde_json::from_value(value).unwrap_or_default();
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Merges the files like a git merge driver: the result is written to `output`.
+/// Returns the unresolved conflicts.
+pub fn merge_files(
+    base_path: &str,
+    ours_path: &str,
+    theirs_path: &str,
+    output: &str,
+) -> Result<Vec<Conflict>, String> {
+    let load = |path: &str| {
+        TreeTable::load_from_file(path).ok_or_else(|| format!("Could not open {path}"))
+    };
+    let merged = merge(&load(base_path)?, &load(ours_path)?, &load(theirs_path)?);
+    std::fs::write(output, merged.to_json()).map_err(|err| format!("{output}: {err}"))?;
+    Ok(merged.conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{ColumnType, RowData};
+
+    const NUMBER: &str = "number";
+
+    fn row(id: &str, number: f64, children: Vec<RowData>) -> RowData {
+        let mut row = RowData::default();
+        row.id = id.to_owned();
+        row.name = id.to_owned();
+        if children.is_empty() {
+            row.col_data.insert(NUMBER.to_owned(), number);
+        }
+        row.children = children;
+        row
+    }
+
+    fn table(children: Vec<RowData>) -> TreeTable {
+        let mut column = ColumnConfig::default();
+        column.id = NUMBER.to_owned();
+        column.col_type = ColumnType::Number;
+        TreeTable {
+            title_text: "".to_owned(),
+            column_configs: vec![column],
+            root_row: row("root", 0.0, children),
+            conflicts: vec![],
+            scenarios: vec![],
+            active_scenario: None,
+            base_values: BTreeMap::new(),
+            parameters: vec![],
+            exchange_rates: vec![],
+        }
+    }
+
+    // a (1, 2), b (3)
+    fn base() -> TreeTable {
+        table(vec![
+            row("a", 0.0, vec![row("1", 1.0, vec![]), row("2", 2.0, vec![])]),
+            row("b", 0.0, vec![row("3", 3.0, vec![])]),
+        ])
+    }
+
+    fn find<'a>(row: &'a RowData, id: &str) -> &'a RowData {
+        FlatTree::new(row).get(id).unwrap().row
+    }
+
+    fn child_ids(table: &TreeTable, id: &str) -> Vec<String> {
+        let row = find(&table.root_row, id);
+        row.children.iter().map(|child| child.id.clone()).collect()
+    }
+
+    fn value(table: &TreeTable, id: &str) -> f64 {
+        find(&table.root_row, id).col_data[NUMBER]
+    }
+
+    fn get_mut<'a>(table: &'a mut TreeTable, id: &str) -> &'a mut RowData {
+        let path = table.root_row.find_path(&|row| row.id == id).unwrap();
+        table.root_row.get_row_mut(&path).unwrap()
+    }
+
+    #[test]
+    fn changes_of_one_side_are_taken() {
+        let mut ours = base();
+        get_mut(&mut ours, "1")
+            .col_data
+            .insert(NUMBER.to_owned(), 10.0);
+        let mut theirs = base();
+        get_mut(&mut theirs, "2")
+            .col_data
+            .insert(NUMBER.to_owned(), 20.0);
+        get_mut(&mut theirs, "b").name = "B".to_owned();
+
+        let merged = merge(&base(), &ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(value(&merged, "1"), 10.0);
+        assert_eq!(value(&merged, "2"), 20.0);
+        assert_eq!(find(&merged.root_row, "b").name, "B");
+    }
+
+    #[test]
+    fn different_changes_of_both_sides_conflict() {
+        let mut ours = base();
+        get_mut(&mut ours, "1")
+            .col_data
+            .insert(NUMBER.to_owned(), 10.0);
+        let mut theirs = base();
+        get_mut(&mut theirs, "1")
+            .col_data
+            .insert(NUMBER.to_owned(), 100.0);
+
+        let mut merged = merge(&base(), &ours, &theirs);
+        assert_eq!(value(&merged, "1"), 10.0);
+        assert_eq!(merged.conflicts.len(), 1);
+        let conflict = &merged.conflicts[0];
+        assert_eq!(conflict.field, ConflictField::Value(NUMBER.to_owned()));
+        assert_eq!(conflict.location, "root / a / 1");
+        assert_eq!(
+            [&conflict.base, &conflict.theirs],
+            [&json!(1.0), &json!(100.0)]
+        );
+
+        merged.resolve_conflict(0, true);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(value(&merged, "1"), 100.0);
+    }
+
+    #[test]
+    fn removed_rows_are_kept_if_the_other_side_changed_them() {
+        let mut ours = base();
+        ours.root_row.children[0].children.remove(0);
+        ours.root_row.children.remove(1);
+        let mut theirs = base();
+        get_mut(&mut theirs, "3")
+            .col_data
+            .insert(NUMBER.to_owned(), 30.0);
+
+        let mut merged = merge(&base(), &ours, &theirs);
+        assert_eq!(child_ids(&merged, "a"), ["2"]);
+        assert_eq!(child_ids(&merged, "b"), ["3"]);
+        assert_eq!(value(&merged, "3"), 30.0);
+        assert_eq!(merged.conflicts.len(), 2);
+        assert!((merged.conflicts.iter()).all(|c| c.field == ConflictField::RowKept));
+
+        // Removing b removes 3 as well
+        let b = (merged.conflicts.iter()).position(|c| c.id == "b").unwrap();
+        merged.resolve_conflict(b, false);
+        assert_eq!(child_ids(&merged, "root"), ["a"]);
+    }
+
+    #[test]
+    fn moved_rows_keep_the_changes_of_the_other_side() {
+        let mut ours = base();
+        let moved = ours.root_row.children[0].children.remove(0);
+        ours.root_row.children[1].children.push(moved);
+        let mut theirs = base();
+        get_mut(&mut theirs, "1")
+            .col_data
+            .insert(NUMBER.to_owned(), 10.0);
+
+        let merged = merge(&base(), &ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(child_ids(&merged, "a"), ["2"]);
+        assert_eq!(child_ids(&merged, "b"), ["3", "1"]);
+        assert_eq!(value(&merged, "1"), 10.0);
+    }
+
+    #[test]
+    fn moves_into_each_other_keep_our_parents() {
+        // Ours moves a below b, theirs moves b below a
+        let mut ours = base();
+        let a = ours.root_row.children.remove(0);
+        ours.root_row.children[0].children.push(a);
+        let mut theirs = base();
+        let b = theirs.root_row.children.remove(1);
+        theirs.root_row.children[0].children.push(b);
+
+        let mut merged = merge(&base(), &ours, &theirs);
+        assert_eq!(child_ids(&merged, "root"), ["b"]);
+        assert_eq!(child_ids(&merged, "b"), ["3", "a"]);
+        assert_eq!(child_ids(&merged, "a"), ["1", "2"]);
+        assert_eq!(merged.conflicts.len(), 1);
+        assert_eq!(merged.conflicts[0].id, "b");
+        assert_eq!(merged.conflicts[0].field, ConflictField::Parent);
+
+        // Taking theirs would move b below itself
+        merged.resolve_conflict(0, true);
+        assert_eq!(child_ids(&merged, "root"), ["b"]);
+    }
+
+    #[test]
+    fn added_children_follow_their_previous_sibling() {
+        let mut ours = base();
+        get_mut(&mut ours, "a")
+            .children
+            .insert(0, row("x", 0.0, vec![]));
+        let mut theirs = base();
+        get_mut(&mut theirs, "a")
+            .children
+            .insert(1, row("y", 0.0, vec![]));
+        get_mut(&mut theirs, "a")
+            .children
+            .insert(0, row("z", 0.0, vec![]));
+
+        let merged = merge(&base(), &ours, &theirs);
+        assert_eq!(child_ids(&merged, "a"), ["z", "x", "1", "y", "2"]);
+    }
+
+    #[test]
+    fn all_fields_of_rows_are_merged() {
+        let mut ours = base();
+        let row = get_mut(&mut ours, "1");
+        row.notes = "Checked".to_owned();
+        row.pinned.insert(NUMBER.to_owned(), 5.0);
+        let mut theirs = base();
+        let row = get_mut(&mut theirs, "1");
+        row.categories.insert("done".to_owned(), "true".to_owned());
+        row.tags.push("urgent".to_owned());
+        row.pinned.insert(NUMBER.to_owned(), 7.0);
+        get_mut(&mut theirs, "a").multiplier_parameter = Some("count".to_owned());
+
+        let mut merged = merge(&base(), &ours, &theirs);
+        let row = find(&merged.root_row, "1");
+        assert_eq!(row.notes, "Checked");
+        assert_eq!(row.categories.get("done").map(String::as_str), Some("true"));
+        assert_eq!(row.tags, ["urgent"]);
+        assert_eq!(row.pinned.get(NUMBER), Some(&5.0));
+        let a = find(&merged.root_row, "a");
+        assert_eq!(a.multiplier_parameter.as_deref(), Some("count"));
+        assert_eq!(merged.conflicts.len(), 1);
+        assert_eq!(
+            merged.conflicts[0].field,
+            ConflictField::Pinned(NUMBER.to_owned())
+        );
+
+        merged.resolve_conflict(0, true);
+        assert_eq!(find(&merged.root_row, "1").pinned.get(NUMBER), Some(&7.0));
+    }
+
+    #[test]
+    fn rows_without_stable_ids_are_matched_by_path() {
+        let mut ours = base();
+        get_mut(&mut ours, "1")
+            .col_data
+            .insert(NUMBER.to_owned(), 10.0);
+        let mut theirs = base();
+        fn new_ids(row: &mut RowData) {
+            row.id = format!("other-{}", row.id);
+            row.children.iter_mut().for_each(new_ids);
+        }
+        new_ids(&mut theirs.root_row);
+        theirs.root_row.children[1].children[0]
+            .col_data
+            .insert(NUMBER.to_owned(), 30.0);
+
+        let merged = merge(&base(), &ours, &theirs);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(child_ids(&merged, "root"), ["a", "b"]);
+        assert_eq!(value(&merged, "1"), 10.0);
+        assert_eq!(value(&merged, "3"), 30.0);
+    }
+}
@@ -0,0 +1,130 @@
+// Physical and monetary units, which values can be converted between.
+//
+// The unit of a column is free text. Only the units listed here can be converted, any other unit
+// is only compatible with itself.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Dimension {
+    Time,
+    Mass,
+    Length,
+    Money,
+}
+
+pub struct Unit {
+    pub symbol: &'static str,
+    pub dimension: Dimension,
+
+    // The size of the unit in the base unit of its dimension
+    pub factor: f64,
+}
+
+const UNITS: [Unit; 10] = [
+    Unit {
+        symbol: "min",
+        dimension: Dimension::Time,
+        factor: 1.0 / 60.0,
+    },
+    Unit {
+        symbol: "h",
+        dimension: Dimension::Time,
+        factor: 1.0,
+    },
+    // A calendar day, not a working day
+    Unit {
+        symbol: "d",
+        dimension: Dimension::Time,
+        factor: 24.0,
+    },
+    Unit {
+        symbol: "g",
+        dimension: Dimension::Mass,
+        factor: 0.001,
+    },
+    Unit {
+        symbol: "kg",
+        dimension: Dimension::Mass,
+        factor: 1.0,
+    },
+    Unit {
+        symbol: "t",
+        dimension: Dimension::Mass,
+        factor: 1000.0,
+    },
+    Unit {
+        symbol: "mm",
+        dimension: Dimension::Length,
+        factor: 0.001,
+    },
+    Unit {
+        symbol: "m",
+        dimension: Dimension::Length,
+        factor: 1.0,
+    },
+    Unit {
+        symbol: "ct",
+        dimension: Dimension::Money,
+        factor: 0.01,
+    },
+    Unit {
+        symbol: "€",
+        dimension: Dimension::Money,
+        factor: 1.0,
+    },
+];
+
+pub fn find(symbol: &str) -> Option<&'static Unit> {
+    UNITS.iter().find(|unit| unit.symbol == symbol.trim())
+}
+
+/// The factor which converts values of one unit to the other, None for incompatible units.
+pub fn conversion_factor(from: &str, to: &str) -> Option<f64> {
+    if from.trim() == to.trim() {
+        return Some(1.0);
+    }
+    let (from, to) = (find(from)?, find(to)?);
+    (from.dimension == to.dimension).then(|| from.factor / to.factor)
+}
+
+/// The units which values of the given unit can be converted to, including the unit itself.
+pub fn compatible_units(symbol: &str) -> Vec<&'static str> {
+    let Some(unit) = find(symbol) else {
+        return vec![];
+    };
+    (UNITS.iter())
+        .filter(|other| other.dimension == unit.dimension)
+        .map(|other| other.symbol)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_of_a_dimension_are_converted() {
+        assert_eq!(conversion_factor("min", "h"), Some(1.0 / 60.0));
+        assert_eq!(conversion_factor("d", "h"), Some(24.0));
+        assert_eq!(conversion_factor("t", "g"), Some(1_000_000.0));
+        assert_eq!(conversion_factor("mm", "m"), Some(0.001));
+        assert_eq!(conversion_factor("ct", "€"), Some(0.01));
+        assert_eq!(conversion_factor(" kg ", "kg"), Some(1.0));
+    }
+
+    #[test]
+    fn units_of_other_dimensions_are_incompatible() {
+        assert_eq!(conversion_factor("h", "kg"), None);
+        assert_eq!(conversion_factor("m", "€"), None);
+        // Unknown units are only compatible with themselves
+        assert_eq!(conversion_factor("pcs", "pcs"), Some(1.0));
+        assert_eq!(conversion_factor("pcs", "kg"), None);
+        assert_eq!(conversion_factor("USD", "€"), None);
+    }
+
+    #[test]
+    fn compatible_units_share_the_dimension() {
+        assert_eq!(compatible_units("h"), ["min", "h", "d"]);
+        assert_eq!(compatible_units("€"), ["ct", "€"]);
+        assert!(compatible_units("pcs").is_empty());
+    }
+}
@@ -0,0 +1,64 @@
+// Exit codes of `tree_tables diff`: 0 if the tables are equal, 1 if they differ, 2 on errors
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde_json::json;
+
+fn document(material: f64) -> String {
+    json!({
+        "title_text": "Offer",
+        "column_configs": [
+            { "id": "material", "caption": "Material", "unit": "€", "col_type": "Number" },
+        ],
+        "root_row": {
+            "id": "root",
+            "name": "∑",
+            "children": [{ "id": "a", "name": "A", "col_data": { "material": material } }],
+        },
+    })
+    .to_string()
+}
+
+fn write(name: &str, json: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tree_tables_diff_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, json).unwrap();
+    path
+}
+
+fn diff(args: &[&PathBuf]) -> i32 {
+    Command::new(env!("CARGO_BIN_EXE_tree_tables"))
+        .arg("diff")
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+        .unwrap()
+}
+
+#[test]
+fn equal_tables_exit_with_0() {
+    let old = write("equal_old.tt", &document(1.0));
+    let new = write("equal_new.tt", &document(1.0));
+    assert_eq!(diff(&[&old, &new]), 0);
+}
+
+#[test]
+fn different_tables_exit_with_1() {
+    let old = write("different_old.tt", &document(1.0));
+    let new = write("different_new.tt", &document(2.0));
+    assert_eq!(diff(&[&old, &new]), 1);
+}
+
+#[test]
+fn errors_exit_with_2() {
+    let old = write("error_old.tt", &document(1.0));
+    let corrupted = write("error_corrupted.tt", "{");
+    let missing = old.with_file_name("missing.tt");
+    assert_eq!(diff(&[&old, &corrupted]), 2);
+    assert_eq!(diff(&[&old, &missing]), 2);
+    assert_eq!(diff(&[&old]), 2);
+}